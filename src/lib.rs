@@ -31,6 +31,8 @@ interleaving of buffer output.
 `Ansi` and `NoColor` both satisfy `WriteColor` for arbitrary implementors of
 `io::Write`. These types are useful when you know exactly what you need. An
 analogous type for the Windows console is not provided since it cannot exist.
+Similarly, `Html` satisfies `WriteColor` by writing HTML instead of escape
+sequences.
 
 # Example: using `StandardStream`
 
@@ -109,6 +111,13 @@ let stdout = StandardStream::stdout(choice);
 ```
 
 Currently, `termcolor` does not provide anything to do this for you.
+
+# Crate features
+
+* **env-cache** - When enabled, the environment variables consulted by
+  [`ColorChoice::Auto`] are read once and the result is cached for the life
+  of the process. The cache can be cleared with
+  [`ColorChoice::refresh_env`]. This is disabled by default.
 */
 
 #![deny(missing_debug_implementations, missing_docs)]
@@ -118,11 +127,23 @@ Currently, `termcolor` does not provide anything to do this for you.
 // #[cfg(doctest)]
 // doctest!("../README.md");
 
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::env;
 use std::error;
+use std::ffi::OsStr;
 use std::fmt;
+use std::fs;
 use std::io::{self, Write};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{FromRawHandle, RawHandle};
 use std::str::FromStr;
+#[cfg(feature = "env-cache")]
+use std::sync::atomic::AtomicU8;
 use std::sync::atomic::{AtomicBool, Ordering};
 #[cfg(windows)]
 use std::sync::{Mutex, MutexGuard};
@@ -144,6 +165,25 @@ pub trait WriteColor: io::Write {
     /// returned.
     fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()>;
 
+    /// Set the color settings of the writer and return whether anything was
+    /// written.
+    ///
+    /// This is like `set_color`, except writers that keep track of the color
+    /// settings they last applied may skip applying identical settings again.
+    /// In that case, nothing is written and `false` is returned. Otherwise,
+    /// the settings are applied and `true` is returned if the writer
+    /// supports colors.
+    ///
+    /// If there was a problem setting the color settings, then an error is
+    /// returned.
+    ///
+    /// This defaults to calling `set_color` and returning the value of
+    /// `supports_color`.
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        self.set_color(spec)?;
+        Ok(self.supports_color())
+    }
+
     /// Reset the current color settings to their original settings.
     ///
     /// If there was a problem resetting the color settings, then an error is
@@ -154,6 +194,81 @@ pub trait WriteColor: io::Write {
     /// [`HyperlinkSpec::none`].
     fn reset(&mut self) -> io::Result<()>;
 
+    /// Set the color settings of the writer, but only if `enabled` is true.
+    ///
+    /// When `enabled` is false, this does nothing and returns `Ok(())`. This
+    /// is a convenience for callers that decide whether to color each piece
+    /// of output separately.
+    fn conditional_color(
+        &mut self,
+        enabled: bool,
+        spec: &ColorSpec,
+    ) -> io::Result<()> {
+        if enabled {
+            self.set_color(spec)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reset the current color settings, but only if `enabled` is true.
+    ///
+    /// When `enabled` is false, this does nothing and returns `Ok(())`. This
+    /// is the counterpart to `conditional_color`.
+    fn conditional_reset(&mut self, enabled: bool) -> io::Result<()> {
+        if enabled {
+            self.reset()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write formatted text with the given color settings, and then reset
+    /// them.
+    ///
+    /// This is typically called with the `format_args!` macro. The reset is
+    /// always attempted, even if setting the color or writing the text
+    /// fails, in which case the first error is returned. If a `Display` or
+    /// `Debug` impl fails without an underlying I/O error, then an error
+    /// with kind `io::ErrorKind::Other` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::{Ansi, Color, ColorSpec, WriteColor};
+    ///
+    /// let mut spec = ColorSpec::new();
+    /// spec.set_fg(Some(Color::Red));
+    ///
+    /// let mut wtr = Ansi::new(vec![]);
+    /// wtr.write_fmt_colored(&spec, format_args!("error: {}", 42))?;
+    /// assert_eq!(wtr.into_inner(), b"\x1B[0m\x1B[31merror: 42\x1B[0m");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    // `io::Error::other` isn't available in our MSRV.
+    #[allow(clippy::io_other_error)]
+    fn write_fmt_colored(
+        &mut self,
+        spec: &ColorSpec,
+        args: fmt::Arguments<'_>,
+    ) -> io::Result<()> {
+        let result = self.set_color(spec).and_then(|()| {
+            let mut adapter = FmtAdapter { wtr: &mut *self, error: Ok(()) };
+            match fmt::write(&mut adapter, args) {
+                Ok(()) => Ok(()),
+                Err(fmt::Error) => match adapter.error {
+                    Err(err) => Err(err),
+                    Ok(()) => Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "formatter error",
+                    )),
+                },
+            }
+        });
+        let reset = self.reset();
+        result.and(reset)
+    }
+
     /// Returns true if and only if the underlying writer must synchronously
     /// interact with an end user's device in order to control colors. By
     /// default, this always returns `false`.
@@ -188,6 +303,27 @@ pub trait WriteColor: io::Write {
         Ok(())
     }
 
+    /// Open a hyperlink with the given [OSC-8] parameters.
+    ///
+    /// This is like calling `set_hyperlink` with [`HyperlinkSpec::open`],
+    /// except it also attaches `key=value` parameters to the hyperlink. The
+    /// most common parameter is `id`, which tells terminals that separate
+    /// pieces of text (such as a link that is wrapped across multiple lines)
+    /// belong to the same hyperlink. The hyperlink is closed in the usual
+    /// way, by calling `set_hyperlink` with [`HyperlinkSpec::close`].
+    ///
+    /// Writers that don't support parameters ignore them. This defaults to
+    /// calling `set_hyperlink` without the parameters.
+    ///
+    /// [OSC-8]: https://github.com/Alhadis/OSC8-Adoption/
+    fn set_hyperlink_with(
+        &mut self,
+        _params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.set_hyperlink(&HyperlinkSpec::open(uri))
+    }
+
     /// Returns true if and only if the underlying writer supports hyperlinks.
     ///
     /// This can be used to avoid generating hyperlink URIs unnecessarily.
@@ -198,45 +334,81 @@ pub trait WriteColor: io::Write {
     }
 }
 
-impl<'a, T: ?Sized + WriteColor> WriteColor for &'a mut T {
+impl<T: ?Sized + WriteColor> WriteColor for &mut T {
     fn supports_color(&self) -> bool {
-        (&**self).supports_color()
+        (**self).supports_color()
     }
     fn supports_hyperlinks(&self) -> bool {
-        (&**self).supports_hyperlinks()
+        (**self).supports_hyperlinks()
     }
     fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
-        (&mut **self).set_color(spec)
+        (**self).set_color(spec)
+    }
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        (**self).try_set_color(spec)
     }
     fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
-        (&mut **self).set_hyperlink(link)
+        (**self).set_hyperlink(link)
+    }
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        (**self).set_hyperlink_with(params, uri)
     }
     fn reset(&mut self) -> io::Result<()> {
-        (&mut **self).reset()
+        (**self).reset()
     }
     fn is_synchronous(&self) -> bool {
-        (&**self).is_synchronous()
+        (**self).is_synchronous()
     }
 }
 
 impl<T: ?Sized + WriteColor> WriteColor for Box<T> {
     fn supports_color(&self) -> bool {
-        (&**self).supports_color()
+        (**self).supports_color()
     }
     fn supports_hyperlinks(&self) -> bool {
-        (&**self).supports_hyperlinks()
+        (**self).supports_hyperlinks()
     }
     fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
-        (&mut **self).set_color(spec)
+        (**self).set_color(spec)
+    }
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        (**self).try_set_color(spec)
     }
     fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
-        (&mut **self).set_hyperlink(link)
+        (**self).set_hyperlink(link)
+    }
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        (**self).set_hyperlink_with(params, uri)
     }
     fn reset(&mut self) -> io::Result<()> {
-        (&mut **self).reset()
+        (**self).reset()
     }
     fn is_synchronous(&self) -> bool {
-        (&**self).is_synchronous()
+        (**self).is_synchronous()
+    }
+}
+
+/// Adapts an `io::Write` to a `fmt::Write`, remembering the I/O error that
+/// caused formatting to fail, if any.
+struct FmtAdapter<'a, W: ?Sized> {
+    wtr: &'a mut W,
+    error: io::Result<()>,
+}
+
+impl<'a, W: io::Write + ?Sized> fmt::Write for FmtAdapter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.wtr.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Err(err);
+            fmt::Error
+        })
     }
 }
 
@@ -245,9 +417,11 @@ impl<T: ?Sized + WriteColor> WriteColor for Box<T> {
 /// The `Default` implementation for this type will select `Auto`, which tries
 /// to do the right thing based on the current environment.
 ///
-/// The `FromStr` implementation for this type converts a lowercase kebab-case
-/// string of the variant name to the corresponding variant. Any other string
-/// results in an error.
+/// The `FromStr` implementation for this type converts a kebab-case string of
+/// the variant name to the corresponding variant, ignoring case. `ansi` is
+/// also accepted as an alias for `always-ansi`. Any other string results in
+/// an error. The `Display` implementation writes the lowercase kebab-case
+/// name of the variant, which can be parsed back again.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ColorChoice {
     /// Try very hard to emit colors. This includes emitting ANSI colors
@@ -259,6 +433,13 @@ pub enum ColorChoice {
     /// Try to use colors, but don't force the issue. If the console isn't
     /// available on Windows, or if TERM=dumb, or if `NO_COLOR` is defined, for
     /// example, then don't use colors.
+    ///
+    /// On Windows, TERM=cygwin causes the console API to be preferred over
+    /// ANSI escape sequences. On other platforms, including Cygwin itself,
+    /// TERM=cygwin is treated like any other terminal that supports colors.
+    ///
+    /// When the `env-cache` feature is enabled, the environment is only
+    /// inspected once. See [`ColorChoice::refresh_env`].
     Auto,
     /// Never emit colors.
     Never,
@@ -277,17 +458,53 @@ impl FromStr for ColorChoice {
     fn from_str(s: &str) -> Result<ColorChoice, ColorChoiceParseError> {
         match s.to_lowercase().as_str() {
             "always" => Ok(ColorChoice::Always),
-            "always-ansi" => Ok(ColorChoice::AlwaysAnsi),
+            "always-ansi" | "ansi" => Ok(ColorChoice::AlwaysAnsi),
             "never" => Ok(ColorChoice::Never),
             "auto" => Ok(ColorChoice::Auto),
-            unknown => Err(ColorChoiceParseError {
-                unknown_choice: unknown.to_string(),
-            }),
+            _ => Err(ColorChoiceParseError { unknown_choice: s.to_string() }),
         }
     }
 }
 
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ColorChoice::Always => "always",
+            ColorChoice::AlwaysAnsi => "always-ansi",
+            ColorChoice::Auto => "auto",
+            ColorChoice::Never => "never",
+        })
+    }
+}
+
+/// The cached result of inspecting the environment for `ColorChoice::Auto`.
+///
+/// This is one of `ENV_UNKNOWN`, `ENV_ALLOWS_COLOR` or `ENV_DENIES_COLOR`.
+#[cfg(feature = "env-cache")]
+static ENV_COLOR: AtomicU8 = AtomicU8::new(ENV_UNKNOWN);
+#[cfg(feature = "env-cache")]
+const ENV_UNKNOWN: u8 = 0;
+#[cfg(feature = "env-cache")]
+const ENV_ALLOWS_COLOR: u8 = 1;
+#[cfg(feature = "env-cache")]
+const ENV_DENIES_COLOR: u8 = 2;
+
 impl ColorChoice {
+    /// Forget any cached decision derived from the environment.
+    ///
+    /// When the `env-cache` feature is enabled, the environment variables
+    /// consulted by `ColorChoice::Auto` (such as `TERM` and `NO_COLOR`) are
+    /// only read once per process. Calling this causes them to be read
+    /// again the next time a writer is created with `ColorChoice::Auto`.
+    /// This is mostly useful in tests that modify the environment.
+    ///
+    /// When the `env-cache` feature is disabled, the environment is read
+    /// every time and this does nothing.
+    pub fn refresh_env() {
+        #[cfg(feature = "env-cache")]
+        ENV_COLOR.store(ENV_UNKNOWN, Ordering::Relaxed);
+    }
+
     /// Returns true if we should attempt to write colored output.
     fn should_attempt_color(&self) -> bool {
         match *self {
@@ -298,14 +515,36 @@ impl ColorChoice {
         }
     }
 
-    #[cfg(not(windows))]
+    #[cfg(feature = "env-cache")]
+    fn env_allows_color(&self) -> bool {
+        // Racing threads may each inspect the environment, but they will
+        // all come to the same conclusion, so this is harmless.
+        match ENV_COLOR.load(Ordering::Relaxed) {
+            ENV_ALLOWS_COLOR => true,
+            ENV_DENIES_COLOR => false,
+            _ => {
+                let yes = self.env_allows_color_uncached();
+                let state =
+                    if yes { ENV_ALLOWS_COLOR } else { ENV_DENIES_COLOR };
+                ENV_COLOR.store(state, Ordering::Relaxed);
+                yes
+            }
+        }
+    }
+
+    #[cfg(not(feature = "env-cache"))]
     fn env_allows_color(&self) -> bool {
+        self.env_allows_color_uncached()
+    }
+
+    #[cfg(not(windows))]
+    fn env_allows_color_uncached(&self) -> bool {
         match env::var_os("TERM") {
             // If TERM isn't set, then we are in a weird environment that
             // probably doesn't support colors.
             None => return false,
             Some(k) => {
-                if k == "dumb" {
+                if term_is_dumb(&k) {
                     return false;
                 }
             }
@@ -319,12 +558,12 @@ impl ColorChoice {
     }
 
     #[cfg(windows)]
-    fn env_allows_color(&self) -> bool {
+    fn env_allows_color_uncached(&self) -> bool {
         // On Windows, if TERM isn't set, then we shouldn't automatically
         // assume that colors aren't allowed. This is unlike Unix environments
         // where TERM is more rigorously set.
         if let Some(k) = env::var_os("TERM") {
-            if k == "dumb" {
+            if term_is_dumb(&k) {
                 return false;
             }
         }
@@ -352,19 +591,37 @@ impl ColorChoice {
                     // cygwin doesn't seem to support ANSI escape sequences
                     // and instead has its own variety. However, the Windows
                     // console API may be available.
-                    Ok(k) => k != "dumb" && k != "cygwin",
+                    Ok(k) => !term_is_dumb(k.as_ref()) && k != "cygwin",
                 }
             }
         }
     }
 }
 
+/// Returns true if the given `TERM` value names a terminal that doesn't
+/// support colors at all.
+///
+/// This check is shared by every platform. Terminals that only lack support
+/// for ANSI escape sequences, such as `cygwin` on Windows, are handled by
+/// `ColorChoice::should_ansi` instead, since the Windows console API may
+/// still be used to color them.
+fn term_is_dumb(term: &OsStr) -> bool {
+    term == "dumb"
+}
+
 /// An error that occurs when parsing a `ColorChoice` fails.
 #[derive(Clone, Debug)]
 pub struct ColorChoiceParseError {
     unknown_choice: String,
 }
 
+impl ColorChoiceParseError {
+    /// Return the string that couldn't be parsed as a valid color choice.
+    pub fn invalid(&self) -> &str {
+        &self.unknown_choice
+    }
+}
+
 impl std::error::Error for ColorChoiceParseError {}
 
 impl fmt::Display for ColorChoiceParseError {
@@ -372,15 +629,27 @@ impl fmt::Display for ColorChoiceParseError {
         write!(
             f,
             "unrecognized color choice '{}': valid choices are: \
-             always, always-ansi, never, auto",
+             always, always-ansi (or ansi), never, auto",
             self.unknown_choice,
         )
     }
 }
 
-/// `std::io` implements `Stdout` and `Stderr` (and their `Lock` variants) as
-/// separate types, which makes it difficult to abstract over them. We use
-/// some simple internal enum types to work around this.
+/// The standard stream that a [`StandardStream`] writes to.
+///
+/// This makes it possible to choose between standard output and standard
+/// error at runtime via [`StandardStream::new`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StandardStreamKind {
+    /// Standard output.
+    Stdout,
+    /// Standard error.
+    Stderr,
+}
+
+// `std::io` implements `Stdout` and `Stderr` (and their `Lock` variants) as
+// separate types, which makes it difficult to abstract over them. We use
+// some simple internal enum types to work around this.
 
 enum StandardStreamType {
     Stdout,
@@ -395,6 +664,7 @@ enum IoStandardStream {
     Stderr(io::Stderr),
     StdoutBuffered(io::BufWriter<io::Stdout>),
     StderrBuffered(io::BufWriter<io::Stderr>),
+    File(fs::File),
 }
 
 impl IoStandardStream {
@@ -425,6 +695,30 @@ impl IoStandardStream {
             IoStandardStream::Stderr(ref s) => {
                 IoStandardStreamLock::StderrLock(s.lock())
             }
+            IoStandardStream::File(ref f) => IoStandardStreamLock::File(f),
+            IoStandardStream::StdoutBuffered(_)
+            | IoStandardStream::StderrBuffered(_) => {
+                // We don't permit this case to ever occur in the public API,
+                // so it's OK to panic.
+                panic!("cannot lock a buffered standard stream")
+            }
+        }
+    }
+
+    /// Like `lock`, but consumes this stream so that the lock returned owns
+    /// all of its resources.
+    ///
+    /// This works because the locks for stdout and stderr already have a
+    /// `'static` lifetime, and files don't require locking.
+    fn into_lock(self) -> IoStandardStreamLock<'static> {
+        match self {
+            IoStandardStream::Stdout(s) => {
+                IoStandardStreamLock::StdoutLock(s.lock())
+            }
+            IoStandardStream::Stderr(s) => {
+                IoStandardStreamLock::StderrLock(s.lock())
+            }
+            IoStandardStream::File(f) => IoStandardStreamLock::OwnedFile(f),
             IoStandardStream::StdoutBuffered(_)
             | IoStandardStream::StderrBuffered(_) => {
                 // We don't permit this case to ever occur in the public API,
@@ -443,6 +737,7 @@ impl io::Write for IoStandardStream {
             IoStandardStream::Stderr(ref mut s) => s.write(b),
             IoStandardStream::StdoutBuffered(ref mut s) => s.write(b),
             IoStandardStream::StderrBuffered(ref mut s) => s.write(b),
+            IoStandardStream::File(ref mut s) => s.write(b),
         }
     }
 
@@ -453,6 +748,7 @@ impl io::Write for IoStandardStream {
             IoStandardStream::Stderr(ref mut s) => s.flush(),
             IoStandardStream::StdoutBuffered(ref mut s) => s.flush(),
             IoStandardStream::StderrBuffered(ref mut s) => s.flush(),
+            IoStandardStream::File(ref mut s) => s.flush(),
         }
     }
 }
@@ -463,6 +759,8 @@ impl io::Write for IoStandardStream {
 enum IoStandardStreamLock<'a> {
     StdoutLock(io::StdoutLock<'a>),
     StderrLock(io::StderrLock<'a>),
+    File(&'a fs::File),
+    OwnedFile(fs::File),
 }
 
 impl<'a> io::Write for IoStandardStreamLock<'a> {
@@ -471,6 +769,8 @@ impl<'a> io::Write for IoStandardStreamLock<'a> {
         match *self {
             IoStandardStreamLock::StdoutLock(ref mut s) => s.write(b),
             IoStandardStreamLock::StderrLock(ref mut s) => s.write(b),
+            IoStandardStreamLock::File(ref mut s) => s.write(b),
+            IoStandardStreamLock::OwnedFile(ref mut s) => s.write(b),
         }
     }
 
@@ -479,6 +779,8 @@ impl<'a> io::Write for IoStandardStreamLock<'a> {
         match *self {
             IoStandardStreamLock::StdoutLock(ref mut s) => s.flush(),
             IoStandardStreamLock::StderrLock(ref mut s) => s.flush(),
+            IoStandardStreamLock::File(ref mut s) => s.flush(),
+            IoStandardStreamLock::OwnedFile(ref mut s) => s.flush(),
         }
     }
 }
@@ -518,6 +820,7 @@ enum WriterInner<W> {
     Windows {
         wtr: W,
         console: Mutex<wincon::Console>,
+        bold_as_intense: bool,
     },
 }
 
@@ -536,11 +839,30 @@ enum WriterInnerLock<'a, W> {
     #[cfg(windows)]
     Windows {
         wtr: W,
-        console: MutexGuard<'a, wincon::Console>,
+        console: ConsoleLock<'a>,
+        bold_as_intense: bool,
     },
 }
 
 impl StandardStream {
+    /// Create a new `StandardStream` with the given color preferences that
+    /// writes to the given standard stream.
+    ///
+    /// This is equivalent to calling [`StandardStream::stdout`] or
+    /// [`StandardStream::stderr`], but permits choosing the stream at
+    /// runtime.
+    pub fn new(
+        kind: StandardStreamKind,
+        choice: ColorChoice,
+    ) -> StandardStream {
+        let sty = match kind {
+            StandardStreamKind::Stdout => StandardStreamType::Stdout,
+            StandardStreamKind::Stderr => StandardStreamType::Stderr,
+        };
+        let wtr = WriterInner::create(sty, choice);
+        StandardStream { wtr: LossyStandardStream::new(wtr) }
+    }
+
     /// Create a new `StandardStream` with the given color preferences that
     /// writes to standard output.
     ///
@@ -567,6 +889,59 @@ impl StandardStream {
         StandardStream { wtr: LossyStandardStream::new(wtr) }
     }
 
+    /// Create a new `StandardStream` with the given color preferences that
+    /// writes to the given file descriptor.
+    ///
+    /// Since there is no way to know what kind of device the file descriptor
+    /// refers to, ANSI escape sequences are always used if coloring is
+    /// desired.
+    ///
+    /// The stream returned takes ownership of the file descriptor and closes
+    /// it when dropped.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a valid file descriptor that is open for writing, and it
+    /// must remain open for the lifetime of the stream returned. Since the
+    /// stream takes ownership of `fd`, nothing else may close it.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(
+        fd: RawFd,
+        choice: ColorChoice,
+    ) -> StandardStream {
+        StandardStream::from_file(fs::File::from_raw_fd(fd), choice)
+    }
+
+    /// Create a new `StandardStream` with the given color preferences that
+    /// writes to the given handle.
+    ///
+    /// Since the handle isn't known to refer to a console, ANSI escape
+    /// sequences are always used if coloring is desired. The Windows console
+    /// APIs are never used.
+    ///
+    /// The stream returned takes ownership of the handle and closes it when
+    /// dropped.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid handle that is open for writing, and it must
+    /// remain open for the lifetime of the stream returned. Since the stream
+    /// takes ownership of `handle`, nothing else may close it.
+    #[cfg(windows)]
+    pub unsafe fn from_raw_handle(
+        handle: RawHandle,
+        choice: ColorChoice,
+    ) -> StandardStream {
+        StandardStream::from_file(fs::File::from_raw_handle(handle), choice)
+    }
+
+    /// Create a new `StandardStream` with the given color preferences that
+    /// writes to the given file.
+    fn from_file(file: fs::File, choice: ColorChoice) -> StandardStream {
+        let wtr = WriterInner::from_file(file, choice);
+        StandardStream { wtr: LossyStandardStream::passthrough(wtr) }
+    }
+
     /// Lock the underlying writer.
     ///
     /// The lock guard returned also satisfies `io::Write` and
@@ -577,6 +952,71 @@ impl StandardStream {
     pub fn lock(&self) -> StandardStreamLock<'_> {
         StandardStreamLock::from_stream(self)
     }
+
+    /// Consume this stream and lock the underlying writer.
+    ///
+    /// Unlike `lock`, the lock guard returned owns the stream, so it isn't
+    /// tied to the lifetime of a borrow. This makes it possible to store the
+    /// lock in a struct or return it from a function. The lock is released
+    /// when the guard is dropped.
+    ///
+    /// All settings of this stream, including the colors it is configured
+    /// to use, are carried over to the lock.
+    pub fn into_lock(self) -> StandardStreamLock<'static> {
+        StandardStreamLock::from_owned_stream(self)
+    }
+
+    /// Consume this stream and return the underlying writer without any
+    /// coloring.
+    ///
+    /// The writer returned writes directly to the same destination as this
+    /// stream (stdout, stderr or the file descriptor or handle it was created
+    /// with). Any colors that are currently set are not reset, so callers
+    /// should call `reset` first if that's desired.
+    ///
+    /// On Windows, if this stream uses the console APIs to set colors, then
+    /// the console handle is dropped and only the raw stream is returned.
+    /// Any subsequent writes therefore appear in whatever colors the console
+    /// was last set to. Also, on Windows, the lossy UTF-8 conversion this
+    /// stream performs when writing to a console is not applied to the
+    /// writer returned.
+    pub fn into_inner(self) -> Box<dyn io::Write + Send> {
+        Box::new(self.wtr.into_inner().into_inner())
+    }
+
+    /// Set whether invalid UTF-8 is replaced when writing to a console.
+    ///
+    /// On Windows, the console can only display text, so by default, any
+    /// invalid UTF-8 written to a stream attached to a console is replaced
+    /// with the Unicode replacement character (`U+FFFD`). Disabling this
+    /// writes all bytes through untouched, which is useful for programs that
+    /// intentionally emit raw bytes. Beware that the console may then display
+    /// garbled text, and that the standard library may reject writes of
+    /// invalid UTF-8 to a console with an error.
+    ///
+    /// This setting is carried over to locks created by `lock`. It has no
+    /// effect on other platforms, where bytes are always written untouched.
+    ///
+    /// This is enabled by default.
+    pub fn set_lossy(&mut self, yes: bool) {
+        self.wtr.set_lossy(yes);
+    }
+
+    /// Set whether bold text is approximated on a Windows console.
+    ///
+    /// A Windows console cannot render bold text. When this is enabled and
+    /// this stream sets colors using the console APIs, bold is approximated
+    /// by using the high intensity variant of the foreground color instead.
+    /// This has no visible effect if no foreground color is set, or if the
+    /// foreground color is already intense. It has no effect on streams that
+    /// use ANSI escape sequences or on other platforms.
+    ///
+    /// This setting is carried over to locks created by `lock`.
+    ///
+    /// This is disabled by default.
+    pub fn set_bold_as_intense(&mut self, yes: bool) {
+        self.wtr.get_mut().set_bold_as_intense(yes);
+    }
 }
 
 impl<'a> StandardStreamLock<'a> {
@@ -584,29 +1024,30 @@ impl<'a> StandardStreamLock<'a> {
     fn from_stream(stream: &StandardStream) -> StandardStreamLock<'_> {
         let locked = match *stream.wtr.get_ref() {
             WriterInner::NoColor(ref w) => {
-                WriterInnerLock::NoColor(NoColor(w.0.lock()))
+                WriterInnerLock::NoColor(NoColor::new(w.wtr.lock()))
             }
             WriterInner::Ansi(ref w) => {
-                WriterInnerLock::Ansi(Ansi(w.0.lock()))
+                WriterInnerLock::Ansi(w.wrap(w.wtr.lock()))
             }
         };
         StandardStreamLock { wtr: stream.wtr.wrap(locked) }
     }
 
     #[cfg(windows)]
-    fn from_stream(stream: &StandardStream) -> StandardStreamLock {
+    fn from_stream(stream: &StandardStream) -> StandardStreamLock<'_> {
         let locked = match *stream.wtr.get_ref() {
             WriterInner::NoColor(ref w) => {
-                WriterInnerLock::NoColor(NoColor(w.0.lock()))
+                WriterInnerLock::NoColor(NoColor::new(w.wtr.lock()))
             }
             WriterInner::Ansi(ref w) => {
-                WriterInnerLock::Ansi(Ansi(w.0.lock()))
+                WriterInnerLock::Ansi(w.wrap(w.wtr.lock()))
             }
             #[cfg(windows)]
-            WriterInner::Windows { ref wtr, ref console } => {
+            WriterInner::Windows { ref wtr, ref console, bold_as_intense } => {
                 WriterInnerLock::Windows {
                     wtr: wtr.lock(),
-                    console: console.lock().unwrap(),
+                    console: ConsoleLock::Guard(console.lock().unwrap()),
+                    bold_as_intense,
                 }
             }
         };
@@ -614,6 +1055,199 @@ impl<'a> StandardStreamLock<'a> {
     }
 }
 
+impl StandardStreamLock<'static> {
+    fn from_owned_stream(
+        stream: StandardStream,
+    ) -> StandardStreamLock<'static> {
+        let wtr = stream.wtr.map(|inner| match inner {
+            WriterInner::NoColor(w) => {
+                WriterInnerLock::NoColor(NoColor::new(w.wtr.into_lock()))
+            }
+            WriterInner::Ansi(w) => {
+                WriterInnerLock::Ansi(w.map(IoStandardStream::into_lock))
+            }
+            #[cfg(windows)]
+            WriterInner::Windows { wtr, console, bold_as_intense } => {
+                WriterInnerLock::Windows {
+                    wtr: wtr.into_lock(),
+                    console: ConsoleLock::Owned(console.into_inner().unwrap()),
+                    bold_as_intense,
+                }
+            }
+        });
+        StandardStreamLock { wtr }
+    }
+}
+
+/// The console used by a locked standard stream on Windows.
+///
+/// A lock created from a borrowed stream holds the stream's mutex guard,
+/// while a lock created by consuming a stream owns the console outright.
+#[cfg(windows)]
+#[derive(Debug)]
+enum ConsoleLock<'a> {
+    Guard(MutexGuard<'a, wincon::Console>),
+    Owned(wincon::Console),
+}
+
+#[cfg(windows)]
+impl<'a> std::ops::Deref for ConsoleLock<'a> {
+    type Target = wincon::Console;
+
+    fn deref(&self) -> &wincon::Console {
+        match *self {
+            ConsoleLock::Guard(ref console) => console,
+            ConsoleLock::Owned(ref console) => console,
+        }
+    }
+}
+
+#[cfg(windows)]
+impl<'a> std::ops::DerefMut for ConsoleLock<'a> {
+    fn deref_mut(&mut self) -> &mut wincon::Console {
+        match *self {
+            ConsoleLock::Guard(ref mut console) => console,
+            ConsoleLock::Owned(ref mut console) => console,
+        }
+    }
+}
+
+/// A builder for configuring a [`StandardStream`].
+///
+/// This collects all of the options that a `StandardStream` supports in one
+/// place. A stream built without changing any of the defaults behaves the
+/// same as one created by [`StandardStream::stdout`] with
+/// `ColorChoice::Auto`.
+///
+/// # Example
+///
+/// ```no_run
+/// use termcolor::{
+///     ColorChoice, ColorLevel, StandardStreamBuilder, StandardStreamKind,
+/// };
+///
+/// let stream = StandardStreamBuilder::new()
+///     .kind(StandardStreamKind::Stderr)
+///     .color_choice(ColorChoice::Always)
+///     .color_level(ColorLevel::Ansi256)
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct StandardStreamBuilder {
+    kind: StandardStreamKind,
+    choice: ColorChoice,
+    lossy: bool,
+    color_level: ColorLevel,
+    palette: Option<Palette>,
+    bold_as_intense: bool,
+}
+
+impl Default for StandardStreamBuilder {
+    fn default() -> StandardStreamBuilder {
+        StandardStreamBuilder::new()
+    }
+}
+
+impl StandardStreamBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> StandardStreamBuilder {
+        StandardStreamBuilder {
+            kind: StandardStreamKind::Stdout,
+            choice: ColorChoice::Auto,
+            lossy: true,
+            color_level: ColorLevel::TrueColor,
+            palette: None,
+            bold_as_intense: false,
+        }
+    }
+
+    /// Build a new `StandardStream` from this configuration.
+    pub fn build(&self) -> StandardStream {
+        let mut stream = StandardStream::new(self.kind, self.choice);
+        stream.set_lossy(self.lossy);
+        stream.set_bold_as_intense(self.bold_as_intense);
+        if let WriterInner::Ansi(ref mut wtr) = *stream.wtr.get_mut() {
+            wtr.set_color_level(self.color_level);
+            wtr.set_palette(self.palette);
+        }
+        stream
+    }
+
+    /// Set the standard stream to write to.
+    ///
+    /// By default, this is `StandardStreamKind::Stdout`.
+    pub fn kind(
+        &mut self,
+        kind: StandardStreamKind,
+    ) -> &mut StandardStreamBuilder {
+        self.kind = kind;
+        self
+    }
+
+    /// Set the color preferences of the stream.
+    ///
+    /// By default, this is `ColorChoice::Auto`.
+    pub fn color_choice(
+        &mut self,
+        choice: ColorChoice,
+    ) -> &mut StandardStreamBuilder {
+        self.choice = choice;
+        self
+    }
+
+    /// Set whether invalid UTF-8 is replaced when writing to a console.
+    ///
+    /// See [`StandardStream::set_lossy`] for details.
+    ///
+    /// This is enabled by default.
+    pub fn lossy(&mut self, yes: bool) -> &mut StandardStreamBuilder {
+        self.lossy = yes;
+        self
+    }
+
+    /// Set the range of colors supported by the terminal being written to.
+    ///
+    /// This only applies when the stream writes ANSI escape sequences. See
+    /// [`Ansi::set_color_level`] for details.
+    ///
+    /// By default, this is `ColorLevel::TrueColor`.
+    pub fn color_level(
+        &mut self,
+        level: ColorLevel,
+    ) -> &mut StandardStreamBuilder {
+        self.color_level = level;
+        self
+    }
+
+    /// Set the palette used to remap the eight named colors.
+    ///
+    /// This only applies when the stream writes ANSI escape sequences. See
+    /// [`Ansi::set_palette`] for details.
+    ///
+    /// By default, no palette is set.
+    pub fn palette(
+        &mut self,
+        palette: Option<Palette>,
+    ) -> &mut StandardStreamBuilder {
+        self.palette = palette;
+        self
+    }
+
+    /// Set whether bold text is approximated on a Windows console.
+    ///
+    /// This only applies when the stream sets colors using the Windows
+    /// console APIs. See [`StandardStream::set_bold_as_intense`] for details.
+    ///
+    /// This is disabled by default.
+    pub fn bold_as_intense(
+        &mut self,
+        yes: bool,
+    ) -> &mut StandardStreamBuilder {
+        self.bold_as_intense = yes;
+        self
+    }
+}
+
 impl BufferedStandardStream {
     /// Create a new `BufferedStandardStream` with the given color preferences
     /// that writes to standard output via a buffered writer.
@@ -653,9 +1287,9 @@ impl WriterInner<IoStandardStream> {
         choice: ColorChoice,
     ) -> WriterInner<IoStandardStream> {
         if choice.should_attempt_color() {
-            WriterInner::Ansi(Ansi(IoStandardStream::new(sty)))
+            WriterInner::Ansi(Ansi::new(IoStandardStream::new(sty)))
         } else {
-            WriterInner::NoColor(NoColor(IoStandardStream::new(sty)))
+            WriterInner::NoColor(NoColor::new(IoStandardStream::new(sty)))
         }
     }
 
@@ -681,17 +1315,60 @@ impl WriterInner<IoStandardStream> {
             .unwrap_or(false);
         if choice.should_attempt_color() {
             if choice.should_ansi() || is_console_virtual {
-                WriterInner::Ansi(Ansi(IoStandardStream::new(sty)))
+                WriterInner::Ansi(Ansi::new(IoStandardStream::new(sty)))
             } else if let Ok(console) = con {
                 WriterInner::Windows {
                     wtr: IoStandardStream::new(sty),
                     console: Mutex::new(console),
+                    bold_as_intense: false,
                 }
             } else {
-                WriterInner::Ansi(Ansi(IoStandardStream::new(sty)))
+                WriterInner::Ansi(Ansi::new(IoStandardStream::new(sty)))
             }
         } else {
-            WriterInner::NoColor(NoColor(IoStandardStream::new(sty)))
+            WriterInner::NoColor(NoColor::new(IoStandardStream::new(sty)))
+        }
+    }
+}
+
+impl WriterInner<IoStandardStream> {
+    /// Create a new inner writer for the given file with the given color
+    /// preferences.
+    ///
+    /// A file is never treated as a Windows console, so ANSI escape
+    /// sequences are used if coloring is desired.
+    fn from_file(
+        file: fs::File,
+        choice: ColorChoice,
+    ) -> WriterInner<IoStandardStream> {
+        if choice.should_attempt_color() {
+            WriterInner::Ansi(Ansi::new(IoStandardStream::File(file)))
+        } else {
+            WriterInner::NoColor(NoColor::new(IoStandardStream::File(file)))
+        }
+    }
+
+    /// Consume this writer and return the stream it writes to.
+    fn into_inner(self) -> IoStandardStream {
+        match self {
+            WriterInner::NoColor(wtr) => wtr.into_inner(),
+            WriterInner::Ansi(wtr) => wtr.into_inner(),
+            #[cfg(windows)]
+            WriterInner::Windows { wtr, .. } => wtr,
+        }
+    }
+
+    /// Set whether bold is approximated by an intense foreground when
+    /// setting colors using the console APIs.
+    #[cfg(not(windows))]
+    fn set_bold_as_intense(&mut self, _yes: bool) {}
+
+    /// Set whether bold is approximated by an intense foreground when
+    /// setting colors using the console APIs.
+    #[cfg(windows)]
+    fn set_bold_as_intense(&mut self, yes: bool) {
+        if let WriterInner::Windows { ref mut bold_as_intense, .. } = *self {
+            *bold_as_intense = yes;
         }
     }
 }
@@ -729,6 +1406,15 @@ impl WriteColor for StandardStream {
         self.wtr.set_hyperlink(link)
     }
 
+    #[inline]
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.wtr.set_hyperlink_with(params, uri)
+    }
+
     #[inline]
     fn reset(&mut self) -> io::Result<()> {
         self.wtr.reset()
@@ -773,6 +1459,15 @@ impl<'a> WriteColor for StandardStreamLock<'a> {
         self.wtr.set_hyperlink(link)
     }
 
+    #[inline]
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.wtr.set_hyperlink_with(params, uri)
+    }
+
     #[inline]
     fn reset(&mut self) -> io::Result<()> {
         self.wtr.reset()
@@ -824,7 +1519,19 @@ impl WriteColor for BufferedStandardStream {
     }
 
     #[inline]
-    fn reset(&mut self) -> io::Result<()> {
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        if self.is_synchronous() {
+            self.wtr.flush()?;
+        }
+        self.wtr.set_hyperlink_with(params, uri)
+    }
+
+    #[inline]
+    fn reset(&mut self) -> io::Result<()> {
         self.wtr.reset()
     }
 
@@ -880,10 +1587,14 @@ impl<W: io::Write> WriteColor for WriterInner<W> {
             WriterInner::NoColor(ref mut wtr) => wtr.set_color(spec),
             WriterInner::Ansi(ref mut wtr) => wtr.set_color(spec),
             #[cfg(windows)]
-            WriterInner::Windows { ref mut wtr, ref console } => {
+            WriterInner::Windows {
+                ref mut wtr,
+                ref console,
+                bold_as_intense,
+            } => {
                 wtr.flush()?;
                 let mut console = console.lock().unwrap();
-                spec.write_console(&mut *console)
+                spec.write_console(&mut *console, bold_as_intense)
             }
         }
     }
@@ -897,14 +1608,31 @@ impl<W: io::Write> WriteColor for WriterInner<W> {
         }
     }
 
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        match *self {
+            WriterInner::NoColor(ref mut wtr) => {
+                wtr.set_hyperlink_with(params, uri)
+            }
+            WriterInner::Ansi(ref mut wtr) => {
+                wtr.set_hyperlink_with(params, uri)
+            }
+            #[cfg(windows)]
+            WriterInner::Windows { .. } => Ok(()),
+        }
+    }
+
     fn reset(&mut self) -> io::Result<()> {
         match *self {
             WriterInner::NoColor(ref mut wtr) => wtr.reset(),
             WriterInner::Ansi(ref mut wtr) => wtr.reset(),
             #[cfg(windows)]
-            WriterInner::Windows { ref mut wtr, ref mut console } => {
+            WriterInner::Windows { ref mut wtr, ref mut console, .. } => {
                 wtr.flush()?;
-                console.lock().unwrap().reset()?;
+                console.get_mut().unwrap().reset()?;
                 Ok(())
             }
         }
@@ -969,9 +1697,13 @@ impl<'a, W: io::Write> WriteColor for WriterInnerLock<'a, W> {
             WriterInnerLock::NoColor(ref mut wtr) => wtr.set_color(spec),
             WriterInnerLock::Ansi(ref mut wtr) => wtr.set_color(spec),
             #[cfg(windows)]
-            WriterInnerLock::Windows { ref mut wtr, ref mut console } => {
+            WriterInnerLock::Windows {
+                ref mut wtr,
+                ref mut console,
+                bold_as_intense,
+            } => {
                 wtr.flush()?;
-                spec.write_console(console)
+                spec.write_console(&mut **console, bold_as_intense)
             }
         }
     }
@@ -986,13 +1718,33 @@ impl<'a, W: io::Write> WriteColor for WriterInnerLock<'a, W> {
         }
     }
 
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        match *self {
+            WriterInnerLock::Unreachable(_) => unreachable!(),
+            WriterInnerLock::NoColor(ref mut wtr) => {
+                wtr.set_hyperlink_with(params, uri)
+            }
+            WriterInnerLock::Ansi(ref mut wtr) => {
+                wtr.set_hyperlink_with(params, uri)
+            }
+            #[cfg(windows)]
+            WriterInnerLock::Windows { .. } => Ok(()),
+        }
+    }
+
     fn reset(&mut self) -> io::Result<()> {
         match *self {
             WriterInnerLock::Unreachable(_) => unreachable!(),
             WriterInnerLock::NoColor(ref mut wtr) => wtr.reset(),
             WriterInnerLock::Ansi(ref mut wtr) => wtr.reset(),
             #[cfg(windows)]
-            WriterInnerLock::Windows { ref mut wtr, ref mut console } => {
+            WriterInnerLock::Windows {
+                ref mut wtr, ref mut console, ..
+            } => {
                 wtr.flush()?;
                 console.reset()?;
                 Ok(())
@@ -1026,10 +1778,37 @@ pub struct BufferWriter {
     printed: AtomicBool,
     separator: Option<Vec<u8>>,
     color_choice: ColorChoice,
+    color_level: ColorLevel,
+    hooks: PrintHooks,
+    pool_size: usize,
+    bold_as_intense: bool,
     #[cfg(windows)]
     console: Option<Mutex<wincon::Console>>,
 }
 
+/// The default maximum number of buffers kept by the pool used by
+/// `BufferWriter::pooled_buffer`.
+const DEFAULT_POOL_SIZE: usize = 4;
+
+thread_local! {
+    /// The storage of dropped `PooledBuffer`s, available for reuse by the
+    /// next call to `BufferWriter::pooled_buffer` on the same thread.
+    static BUFFER_POOL: RefCell<Vec<Vec<u8>>> = const { RefCell::new(vec![]) };
+}
+
+/// A hook registered on a `BufferWriter` via `on_print`.
+type PrintHook = Box<dyn Fn(&Buffer) + Send + Sync>;
+
+/// The hooks registered on a `BufferWriter` via `on_print`.
+#[derive(Default)]
+struct PrintHooks(Vec<PrintHook>);
+
+impl fmt::Debug for PrintHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PrintHooks").field("len", &self.0.len()).finish()
+    }
+}
+
 impl BufferWriter {
     /// Create a new `BufferWriter` that writes to a standard stream with the
     /// given color preferences.
@@ -1043,6 +1822,10 @@ impl BufferWriter {
             printed: AtomicBool::new(false),
             separator: None,
             color_choice: choice,
+            color_level: ColorLevel::TrueColor,
+            hooks: PrintHooks::default(),
+            pool_size: DEFAULT_POOL_SIZE,
+            bold_as_intense: false,
         }
     }
 
@@ -1078,6 +1861,10 @@ impl BufferWriter {
             printed: AtomicBool::new(false),
             separator: None,
             color_choice: choice,
+            color_level: ColorLevel::TrueColor,
+            hooks: PrintHooks::default(),
+            pool_size: DEFAULT_POOL_SIZE,
+            bold_as_intense: false,
             console: con.map(Mutex::new),
         }
     }
@@ -1114,13 +1901,101 @@ impl BufferWriter {
         self.separator = sep;
     }
 
+    /// Set the range of colors used by buffers created by this writer.
+    ///
+    /// Buffers that use ANSI escape sequences downgrade any colors that
+    /// aren't supported at the given level, as described by
+    /// [`Ansi::set_color_level`]. This is independent of the `ColorChoice`
+    /// given, which still determines whether colors are used at all. Buffers
+    /// that write to a Windows console are unaffected.
+    ///
+    /// By default, this is `ColorLevel::TrueColor`.
+    pub fn set_color_level(&mut self, level: ColorLevel) {
+        self.color_level = level;
+    }
+
+    /// Set whether bold text is approximated on a Windows console.
+    ///
+    /// A Windows console cannot render bold text. When this is enabled,
+    /// buffers that are printed using the console APIs approximate bold by
+    /// using the high intensity variant of the foreground color instead. This
+    /// has no visible effect if no foreground color is set, or if the
+    /// foreground color is already intense. It has no effect on buffers that
+    /// use ANSI escape sequences or on other platforms.
+    ///
+    /// This is disabled by default.
+    pub fn set_bold_as_intense(&mut self, yes: bool) {
+        self.bold_as_intense = yes;
+    }
+
+    /// Register a hook that is called after each successful call to `print`.
+    ///
+    /// The hook is given the buffer that was just printed. It is called while
+    /// the lock on the underlying stream is still held, so hooks observe
+    /// buffers in the same order in which they were written. Consequently,
+    /// a hook must not print to this writer, or else it will deadlock.
+    ///
+    /// Multiple hooks may be registered. They are called in the order in
+    /// which they were registered. Hooks are not called for empty buffers,
+    /// since printing an empty buffer does nothing.
+    pub fn on_print<F>(&mut self, hook: F)
+    where
+        F: Fn(&Buffer) + Send + Sync + 'static,
+    {
+        self.hooks.0.push(Box::new(hook));
+    }
+
+    /// Set the maximum number of buffers kept for reuse by the pool used by
+    /// `pooled_buffer`.
+    ///
+    /// Each thread has its own pool. When a `PooledBuffer` is dropped while
+    /// its thread's pool already holds this many buffers, its storage is
+    /// freed instead of being kept. Setting this to `0` disables pooling.
+    ///
+    /// By default, this is `4`.
+    pub fn pool_size(&mut self, size: usize) {
+        self.pool_size = size;
+    }
+
+    /// Creates a new `Buffer` with the current color preferences, reusing the
+    /// storage of a previously dropped `PooledBuffer` when possible.
+    ///
+    /// This is like `buffer`, except the storage of the buffer is returned to
+    /// a thread local pool when the `PooledBuffer` is dropped. Programs that
+    /// create many short lived buffers can use this to avoid allocating new
+    /// storage for each one. When the pool is empty, the buffer starts with
+    /// no storage, just like a buffer returned by `buffer`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use termcolor::{BufferWriter, ColorChoice};
+    ///
+    /// let bufwtr = BufferWriter::stdout(ColorChoice::Never);
+    /// for i in 0..3 {
+    ///     let mut buf = bufwtr.pooled_buffer();
+    ///     writeln!(buf, "line {}", i)?;
+    ///     bufwtr.print(&buf)?;
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn pooled_buffer(&self) -> PooledBuffer {
+        let mut buf = self.buffer();
+        if let Some(storage) = BUFFER_POOL.with(|pool| pool.borrow_mut().pop())
+        {
+            buf.replace_storage(storage);
+        }
+        PooledBuffer { buf, pool_size: self.pool_size }
+    }
+
     /// Creates a new `Buffer` with the current color preferences.
     ///
     /// A `Buffer` satisfies both `io::Write` and `WriteColor`. A `Buffer` can
     /// be printed using the `print` method.
     #[cfg(not(windows))]
     pub fn buffer(&self) -> Buffer {
-        Buffer::new(self.color_choice)
+        Buffer::new(self.color_choice, self.color_level)
     }
 
     /// Creates a new `Buffer` with the current color preferences.
@@ -1129,7 +2004,11 @@ impl BufferWriter {
     /// be printed using the `print` method.
     #[cfg(windows)]
     pub fn buffer(&self) -> Buffer {
-        Buffer::new(self.color_choice, self.console.is_some())
+        Buffer::new(
+            self.color_choice,
+            self.console.is_some(),
+            self.color_level,
+        )
     }
 
     /// Prints the contents of the given buffer.
@@ -1149,8 +2028,8 @@ impl BufferWriter {
             }
         }
         match buf.0 {
-            BufferInner::NoColor(ref b) => stream.write_all(&b.0)?,
-            BufferInner::Ansi(ref b) => stream.write_all(&b.0)?,
+            BufferInner::NoColor(ref b) => stream.write_all(&b.wtr)?,
+            BufferInner::Ansi(ref b) => stream.write_all(&b.wtr)?,
             #[cfg(windows)]
             BufferInner::Windows(ref b) => {
                 // We guarantee by construction that we have a console here.
@@ -1160,14 +2039,115 @@ impl BufferWriter {
                     .as_ref()
                     .expect("got Windows buffer but have no Console");
                 let mut console = console_mutex.lock().unwrap();
-                b.print(&mut *console, &mut stream)?;
+                b.print(&mut *console, &mut stream, self.bold_as_intense)?;
             }
         }
         self.printed.store(true, Ordering::Relaxed);
+        for hook in &self.hooks.0 {
+            hook(buf);
+        }
         Ok(())
     }
 }
 
+/// A `Buffer` whose storage is returned to a thread local pool when dropped.
+///
+/// This is created by [`BufferWriter::pooled_buffer`], and dereferences to
+/// a `Buffer`. Like a `Buffer`, it implements `io::Write` and `WriteColor`,
+/// so it can be passed anywhere either is expected.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Buffer,
+    pool_size: usize,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Buffer;
+
+    fn deref(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Buffer {
+        &mut self.buf
+    }
+}
+
+impl io::Write for PooledBuffer {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.buf.flush()
+    }
+}
+
+impl WriteColor for PooledBuffer {
+    #[inline]
+    fn supports_color(&self) -> bool {
+        self.buf.supports_color()
+    }
+
+    #[inline]
+    fn supports_hyperlinks(&self) -> bool {
+        self.buf.supports_hyperlinks()
+    }
+
+    #[inline]
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.buf.set_color(spec)
+    }
+
+    #[inline]
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        self.buf.try_set_color(spec)
+    }
+
+    #[inline]
+    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
+        self.buf.set_hyperlink(link)
+    }
+
+    #[inline]
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.buf.set_hyperlink_with(params, uri)
+    }
+
+    #[inline]
+    fn reset(&mut self) -> io::Result<()> {
+        self.buf.reset()
+    }
+
+    #[inline]
+    fn is_synchronous(&self) -> bool {
+        self.buf.is_synchronous()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.buf.clear();
+        let storage = self.buf.replace_storage(vec![]);
+        // The pool may already be gone if this is dropped while its thread
+        // is exiting, in which case the storage is simply freed.
+        let _ = BUFFER_POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < self.pool_size {
+                pool.push(storage);
+            }
+        });
+    }
+}
+
 /// Write colored text to memory.
 ///
 /// `Buffer` is a platform independent abstraction for printing colored text to
@@ -1200,9 +2180,9 @@ enum BufferInner {
 impl Buffer {
     /// Create a new buffer with the given color settings.
     #[cfg(not(windows))]
-    fn new(choice: ColorChoice) -> Buffer {
+    fn new(choice: ColorChoice, level: ColorLevel) -> Buffer {
         if choice.should_attempt_color() {
-            Buffer::ansi()
+            Buffer::ansi_with_level(level)
         } else {
             Buffer::no_color()
         }
@@ -1216,10 +2196,10 @@ impl Buffer {
     /// If coloring is desired and `console` is false, then ANSI escape
     /// sequences are used instead.
     #[cfg(windows)]
-    fn new(choice: ColorChoice, console: bool) -> Buffer {
+    fn new(choice: ColorChoice, console: bool, level: ColorLevel) -> Buffer {
         if choice.should_attempt_color() {
             if !console || choice.should_ansi() {
-                Buffer::ansi()
+                Buffer::ansi_with_level(level)
             } else {
                 Buffer::console()
             }
@@ -1230,12 +2210,20 @@ impl Buffer {
 
     /// Create a buffer that drops all color information.
     pub fn no_color() -> Buffer {
-        Buffer(BufferInner::NoColor(NoColor(vec![])))
+        Buffer(BufferInner::NoColor(NoColor::new(vec![])))
     }
 
     /// Create a buffer that uses ANSI escape sequences.
     pub fn ansi() -> Buffer {
-        Buffer(BufferInner::Ansi(Ansi(vec![])))
+        Buffer(BufferInner::Ansi(Ansi::new(vec![])))
+    }
+
+    /// Create a buffer that uses ANSI escape sequences limited to the given
+    /// color level.
+    fn ansi_with_level(level: ColorLevel) -> Buffer {
+        let mut wtr = Ansi::new(vec![]);
+        wtr.set_color_level(level);
+        Buffer(BufferInner::Ansi(wtr))
     }
 
     /// Create a buffer that can be written to a Windows console.
@@ -1252,8 +2240,8 @@ impl Buffer {
     /// Returns the length of this buffer in bytes.
     pub fn len(&self) -> usize {
         match self.0 {
-            BufferInner::NoColor(ref b) => b.0.len(),
-            BufferInner::Ansi(ref b) => b.0.len(),
+            BufferInner::NoColor(ref b) => b.wtr.len(),
+            BufferInner::Ansi(ref b) => b.wtr.len(),
             #[cfg(windows)]
             BufferInner::Windows(ref b) => b.buf.len(),
         }
@@ -1262,31 +2250,248 @@ impl Buffer {
     /// Clears this buffer.
     pub fn clear(&mut self) {
         match self.0 {
-            BufferInner::NoColor(ref mut b) => b.0.clear(),
-            BufferInner::Ansi(ref mut b) => b.0.clear(),
+            BufferInner::NoColor(ref mut b) => b.wtr.clear(),
+            BufferInner::Ansi(ref mut b) => {
+                b.wtr.clear();
+                b.last_spec = None;
+            }
             #[cfg(windows)]
             BufferInner::Windows(ref mut b) => b.clear(),
         }
     }
 
+    /// Append the contents of the given buffer to the end of this buffer.
+    ///
+    /// If both buffers use the same kind of coloring, then all color
+    /// information is preserved. Otherwise, only the text of `other` is
+    /// appended and its color information is dropped. For example, when
+    /// appending a buffer that uses ANSI escape sequences to a buffer that
+    /// ignores colors, the escape sequences are stripped.
+    pub fn append(&mut self, other: &Buffer) {
+        match (&mut self.0, &other.0) {
+            (BufferInner::NoColor(ref mut b), BufferInner::NoColor(ref o)) => {
+                b.wtr.extend_from_slice(&o.wtr)
+            }
+            (BufferInner::Ansi(ref mut b), BufferInner::Ansi(ref o)) => {
+                b.wtr.extend_from_slice(&o.wtr);
+                // The color settings in effect are now whatever `other` left
+                // them as.
+                b.last_spec = None;
+            }
+            #[cfg(windows)]
+            (BufferInner::Windows(ref mut b), BufferInner::Windows(ref o)) => {
+                b.append(o)
+            }
+            (_, _) => {
+                let mut text = vec![];
+                match other.0 {
+                    BufferInner::NoColor(ref o) => {
+                        text.extend_from_slice(&o.wtr)
+                    }
+                    BufferInner::Ansi(ref o) => {
+                        for seg in AnsiSegments::new(&o.wtr) {
+                            if let AnsiSegment::Text(t) = seg {
+                                text.extend_from_slice(t);
+                            }
+                        }
+                    }
+                    #[cfg(windows)]
+                    BufferInner::Windows(ref o) => {
+                        text.extend_from_slice(&o.buf)
+                    }
+                }
+                match self.0 {
+                    BufferInner::NoColor(ref mut b) => b.wtr.extend(text),
+                    BufferInner::Ansi(ref mut b) => b.wtr.extend(text),
+                    #[cfg(windows)]
+                    BufferInner::Windows(ref mut b) => b.buf.extend(text),
+                }
+            }
+        }
+    }
+
+    /// Apply the contents of this buffer to the given writer by replaying it.
+    ///
+    /// The text in this buffer is written to `wtr`, and the color settings
+    /// are applied by calling `set_color` and `reset` on `wtr` at the
+    /// positions where they were set. This makes it possible to convert a
+    /// buffer to a different format, for example, by replaying it to an
+    /// [`Html`] writer.
+    ///
+    /// For buffers that use ANSI escape sequences, the escape sequences are
+    /// parsed to recover the color settings. SGR sequences that are
+    /// immediately adjacent to one another are combined into a single call
+    /// to `set_color`. OSC 8 hyperlinks are replayed via `set_hyperlink`, or
+    /// via `set_hyperlink_with` when they have parameters (such as `id`).
+    /// Parameters that aren't valid UTF-8 `key=value` pairs are dropped. All
+    /// other escape sequences are written to `wtr` as is, so applying an
+    /// ANSI buffer to another ANSI buffer reproduces it exactly.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use termcolor::{Buffer, Color, ColorSpec, Html, WriteColor};
+    ///
+    /// let mut buf = Buffer::ansi();
+    /// buf.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+    /// write!(buf, "error")?;
+    /// buf.reset()?;
+    ///
+    /// let mut html = Html::new(vec![]);
+    /// buf.apply_to(&mut html)?;
+    /// assert_eq!(
+    ///     html.into_inner(),
+    ///     b"<span style=\"color:#cd0000\">error</span>",
+    /// );
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn apply_to<W: WriteColor + ?Sized>(
+        &self,
+        wtr: &mut W,
+    ) -> io::Result<()> {
+        match self.0 {
+            BufferInner::NoColor(ref b) => wtr.write_all(&b.wtr),
+            BufferInner::Ansi(ref b) => {
+                for event in AnsiEvents::new(&b.wtr) {
+                    match event {
+                        AnsiEvent::Text(text) => wtr.write_all(text)?,
+                        AnsiEvent::Color(Some(spec)) => {
+                            wtr.set_color(&spec)?
+                        }
+                        AnsiEvent::Color(None) => wtr.reset()?,
+                        AnsiEvent::Hyperlink(params, Some(uri)) => {
+                            match AnsiEvents::hyperlink_params(params) {
+                                Some(ref params) if !params.is_empty() => {
+                                    wtr.set_hyperlink_with(params, uri)?
+                                }
+                                _ => wtr.set_hyperlink(
+                                    &HyperlinkSpec::open(uri),
+                                )?,
+                            }
+                        }
+                        AnsiEvent::Hyperlink(_, None) => {
+                            wtr.set_hyperlink(&HyperlinkSpec::close())?
+                        }
+                        AnsiEvent::Escape(esc) => wtr.write_all(esc)?,
+                    }
+                }
+                Ok(())
+            }
+            #[cfg(windows)]
+            BufferInner::Windows(ref b) => {
+                let mut last = 0;
+                for &(pos, ref spec) in &b.colors {
+                    wtr.write_all(&b.buf[last..pos])?;
+                    last = pos;
+                    match *spec {
+                        Some(ref spec) => wtr.set_color(spec)?,
+                        None => wtr.reset()?,
+                    }
+                }
+                wtr.write_all(&b.buf[last..])
+            }
+        }
+    }
+
+    /// Returns the positions at which the color settings of this buffer
+    /// change.
+    ///
+    /// Each span is a byte offset into the text of this buffer along with
+    /// the color settings applied at that offset, where `None` means the
+    /// settings are reset. For buffers that use ANSI escape sequences, the
+    /// offsets are into the text with all escape sequences removed, and the
+    /// spans are recovered by parsing the SGR sequences in the same way as
+    /// [`Buffer::apply_to`]. Buffers that don't support color have no spans.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use termcolor::{Buffer, Color, ColorSpec, WriteColor};
+    ///
+    /// let mut spec = ColorSpec::new();
+    /// spec.set_fg(Some(Color::Red));
+    ///
+    /// let mut buf = Buffer::ansi();
+    /// write!(buf, "a ")?;
+    /// buf.set_color(&spec)?;
+    /// write!(buf, "red")?;
+    /// buf.reset()?;
+    /// write!(buf, " word")?;
+    ///
+    /// assert_eq!(buf.color_spans(), vec![(2, Some(spec)), (5, None)]);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn color_spans(&self) -> Vec<(usize, Option<ColorSpec>)> {
+        match self.0 {
+            BufferInner::NoColor(_) => vec![],
+            BufferInner::Ansi(ref b) => {
+                let mut pos = 0;
+                let mut spans = vec![];
+                for event in AnsiEvents::new(&b.wtr) {
+                    match event {
+                        AnsiEvent::Text(text) => pos += text.len(),
+                        AnsiEvent::Color(spec) => spans.push((pos, spec)),
+                        AnsiEvent::Hyperlink(..) | AnsiEvent::Escape(_) => {}
+                    }
+                }
+                spans
+            }
+            #[cfg(windows)]
+            BufferInner::Windows(ref b) => b.colors.clone(),
+        }
+    }
+
     /// Consume this buffer and return the underlying raw data.
     ///
     /// On Windows, this unrecoverably drops all color information associated
     /// with the buffer.
     pub fn into_inner(self) -> Vec<u8> {
         match self.0 {
-            BufferInner::NoColor(b) => b.0,
-            BufferInner::Ansi(b) => b.0,
+            BufferInner::NoColor(b) => b.wtr,
+            BufferInner::Ansi(b) => b.wtr,
             #[cfg(windows)]
             BufferInner::Windows(b) => b.buf,
         }
     }
 
+    /// Consume this buffer and return its underlying data as a string.
+    ///
+    /// Any invalid UTF-8 is replaced with the Unicode replacement character
+    /// (`U+FFFD`). If the data is already valid UTF-8, then it is not
+    /// copied. Like `into_inner`, this includes any ANSI escape sequences
+    /// written to the buffer.
+    ///
+    /// On Windows, this unrecoverably drops all color information associated
+    /// with the buffer.
+    pub fn into_lossy_string(self) -> String {
+        match String::from_utf8(self.into_inner()) {
+            Ok(s) => s,
+            Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+        }
+    }
+
+    /// Replace the storage of this buffer's data, returning the previous
+    /// storage.
+    fn replace_storage(&mut self, storage: Vec<u8>) -> Vec<u8> {
+        match self.0 {
+            BufferInner::NoColor(ref mut b) => {
+                mem::replace(&mut b.wtr, storage)
+            }
+            BufferInner::Ansi(ref mut b) => mem::replace(&mut b.wtr, storage),
+            #[cfg(windows)]
+            BufferInner::Windows(ref mut b) => {
+                mem::replace(&mut b.buf, storage)
+            }
+        }
+    }
+
     /// Return the underlying data of the buffer.
     pub fn as_slice(&self) -> &[u8] {
         match self.0 {
-            BufferInner::NoColor(ref b) => &b.0,
-            BufferInner::Ansi(ref b) => &b.0,
+            BufferInner::NoColor(ref b) => &b.wtr,
+            BufferInner::Ansi(ref b) => &b.wtr,
             #[cfg(windows)]
             BufferInner::Windows(ref b) => &b.buf,
         }
@@ -1295,8 +2500,8 @@ impl Buffer {
     /// Return the underlying data of the buffer as a mutable slice.
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
         match self.0 {
-            BufferInner::NoColor(ref mut b) => &mut b.0,
-            BufferInner::Ansi(ref mut b) => &mut b.0,
+            BufferInner::NoColor(ref mut b) => &mut b.wtr,
+            BufferInner::Ansi(ref mut b) => &mut b.wtr,
             #[cfg(windows)]
             BufferInner::Windows(ref mut b) => &mut b.buf,
         }
@@ -1325,6 +2530,25 @@ impl io::Write for Buffer {
     }
 }
 
+/// Appends bytes to this buffer without any coloring, just like writing them
+/// via `io::Write`.
+impl Extend<u8> for Buffer {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        match self.0 {
+            BufferInner::NoColor(ref mut w) => w.get_mut().extend(iter),
+            BufferInner::Ansi(ref mut w) => w.get_mut().extend(iter),
+            #[cfg(windows)]
+            BufferInner::Windows(ref mut w) => w.buf.extend(iter),
+        }
+    }
+}
+
+impl<'a> Extend<&'a u8> for Buffer {
+    fn extend<I: IntoIterator<Item = &'a u8>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
 impl WriteColor for Buffer {
     #[inline]
     fn supports_color(&self) -> bool {
@@ -1356,6 +2580,16 @@ impl WriteColor for Buffer {
         }
     }
 
+    #[inline]
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        match self.0 {
+            BufferInner::NoColor(ref mut w) => w.try_set_color(spec),
+            BufferInner::Ansi(ref mut w) => w.try_set_color(spec),
+            #[cfg(windows)]
+            BufferInner::Windows(ref mut w) => w.try_set_color(spec),
+        }
+    }
+
     #[inline]
     fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
         match self.0 {
@@ -1366,6 +2600,24 @@ impl WriteColor for Buffer {
         }
     }
 
+    #[inline]
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        match self.0 {
+            BufferInner::NoColor(ref mut w) => {
+                w.set_hyperlink_with(params, uri)
+            }
+            BufferInner::Ansi(ref mut w) => w.set_hyperlink_with(params, uri),
+            #[cfg(windows)]
+            BufferInner::Windows(ref mut w) => {
+                w.set_hyperlink_with(params, uri)
+            }
+        }
+    }
+
     #[inline]
     fn reset(&mut self) -> io::Result<()> {
         match self.0 {
@@ -1383,41 +2635,71 @@ impl WriteColor for Buffer {
 }
 
 /// Satisfies `WriteColor` but ignores all color options.
+///
+/// By default, all color settings are silently ignored. This can be changed
+/// with [`NoColor::strict`], which makes attempts to set colors fail instead.
 #[derive(Clone, Debug)]
-pub struct NoColor<W>(W);
+pub struct NoColor<W> {
+    wtr: W,
+    strict: bool,
+}
 
 impl<W: Write> NoColor<W> {
     /// Create a new writer that satisfies `WriteColor` but drops all color
     /// information.
     pub fn new(wtr: W) -> NoColor<W> {
-        NoColor(wtr)
+        NoColor { wtr, strict: false }
     }
 
     /// Consume this `NoColor` value and return the inner writer.
     pub fn into_inner(self) -> W {
-        self.0
+        self.wtr
     }
 
     /// Return a reference to the inner writer.
     pub fn get_ref(&self) -> &W {
-        &self.0
+        &self.wtr
     }
 
     /// Return a mutable reference to the inner writer.
     pub fn get_mut(&mut self) -> &mut W {
-        &mut self.0
+        &mut self.wtr
+    }
+
+    /// When enabled, `set_color` and `reset` return an error instead of
+    /// silently doing nothing.
+    ///
+    /// The error returned has kind `io::ErrorKind::Unsupported`. This is
+    /// useful during development to catch code that unexpectedly tries to
+    /// use colors with a writer that doesn't support them. Hyperlinks are
+    /// still silently ignored.
+    ///
+    /// This is disabled by default.
+    pub fn strict(&mut self, yes: bool) -> &mut NoColor<W> {
+        self.strict = yes;
+        self
+    }
+
+    fn check_strict(&self, what: &str) -> io::Result<()> {
+        if !self.strict {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} is not supported by a strict NoColor writer", what),
+        ))
     }
 }
 
 impl<W: io::Write> io::Write for NoColor<W> {
     #[inline]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        self.wtr.write(buf)
     }
 
     #[inline]
     fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+        self.wtr.flush()
     }
 }
 
@@ -1434,7 +2716,7 @@ impl<W: io::Write> WriteColor for NoColor<W> {
 
     #[inline]
     fn set_color(&mut self, _: &ColorSpec) -> io::Result<()> {
-        Ok(())
+        self.check_strict("setting colors")
     }
 
     #[inline]
@@ -1444,7 +2726,7 @@ impl<W: io::Write> WriteColor for NoColor<W> {
 
     #[inline]
     fn reset(&mut self) -> io::Result<()> {
-        Ok(())
+        self.check_strict("resetting colors")
     }
 
     #[inline]
@@ -1455,51 +2737,394 @@ impl<W: io::Write> WriteColor for NoColor<W> {
 
 /// Satisfies `WriteColor` using standard ANSI escape sequences.
 #[derive(Clone, Debug)]
-pub struct Ansi<W>(W);
+pub struct Ansi<W> {
+    wtr: W,
+    flush_on_reset: bool,
+    palette: Option<Palette>,
+    color_level: ColorLevel,
+    dedup_colors: bool,
+    last_spec: Option<ColorSpec>,
+    reset_sequence: Option<Vec<u8>>,
+    reset_clears_hyperlink: bool,
+    hyperlink_open: bool,
+}
 
 impl<W: Write> Ansi<W> {
     /// Create a new writer that satisfies `WriteColor` using standard ANSI
     /// escape sequences.
     pub fn new(wtr: W) -> Ansi<W> {
-        Ansi(wtr)
+        Ansi {
+            wtr,
+            flush_on_reset: false,
+            palette: None,
+            color_level: ColorLevel::TrueColor,
+            dedup_colors: false,
+            last_spec: None,
+            reset_sequence: None,
+            reset_clears_hyperlink: false,
+            hyperlink_open: false,
+        }
     }
 
     /// Consume this `Ansi` value and return the inner writer.
     pub fn into_inner(self) -> W {
-        self.0
+        self.wtr
     }
 
     /// Return a reference to the inner writer.
     pub fn get_ref(&self) -> &W {
-        &self.0
+        &self.wtr
     }
 
     /// Return a mutable reference to the inner writer.
     pub fn get_mut(&mut self) -> &mut W {
-        &mut self.0
+        &mut self.wtr
     }
-}
 
-impl<W: io::Write> io::Write for Ansi<W> {
-    #[inline]
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+    /// When enabled, the inner writer is flushed after every call to
+    /// `reset`.
+    ///
+    /// This is useful when the inner writer is buffered (for example, with
+    /// a `BufWriter` or `LineWriter`) and colored output should become
+    /// visible as soon as each colored span is finished. Note that
+    /// flushing typically requires a system call, so enabling this may
+    /// noticeably reduce throughput when colors are reset frequently.
+    ///
+    /// The reset that `set_color` emits before applying a spec does not
+    /// trigger a flush. Only explicit calls to `reset` do.
+    ///
+    /// This is disabled by default.
+    pub fn flush_on_reset(&mut self, yes: bool) -> &mut Ansi<W> {
+        self.flush_on_reset = yes;
+        self
     }
 
-    // Adding this method here is not required because it has a default impl,
-    // but it seems to provide a perf improvement in some cases when using
-    // a `BufWriter` with lots of writes.
-    //
-    // See https://github.com/BurntSushi/termcolor/pull/56 for more details
-    // and a minimized example.
-    #[inline]
-    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        self.0.write_all(buf)
-    }
+    /// When enabled, `set_color` skips writing color settings that are
+    /// identical to the settings it most recently applied.
+    ///
+    /// This avoids writing redundant escape sequences when the same color
+    /// settings are applied repeatedly. The settings are forgotten whenever
+    /// this writer knows that the terminal may be in a different state, such
+    /// as after `reset`, `restore_cursor` or switching screens. However,
+    /// escape sequences written directly to this writer (e.g., via
+    /// `io::Write`) or to another writer for the same terminal are not
+    /// tracked, so this should only be enabled when this writer is the only
+    /// one that changes the terminal's colors.
+    ///
+    /// This is disabled by default.
+    pub fn set_dedup_colors(&mut self, yes: bool) -> &mut Ansi<W> {
+        self.dedup_colors = yes;
+        self.last_spec = None;
+        self
+    }
+
+    /// Set the palette used to remap the eight named colors.
+    ///
+    /// When a palette is set, any named color that it maps to a replacement
+    /// is written as that replacement instead. This makes it possible to
+    /// apply a theme without changing the colors used at each call site.
+    /// Setting the palette to `None` restores the default behavior.
+    ///
+    /// By default, no palette is set.
+    pub fn set_palette(&mut self, palette: Option<Palette>) -> &mut Ansi<W> {
+        self.palette = palette;
+        self.last_spec = None;
+        self
+    }
+
+    /// Set the range of colors supported by the terminal being written to.
+    ///
+    /// Colors that aren't supported at the given level are downgraded to the
+    /// closest supported color before being written, in the same way as
+    /// [`ColorSpec::to_lossy`]. At `ColorLevel::Basic`, only the standard 8
+    /// color codes and their bright variants are emitted (that is, `30-37`,
+    /// `90-97` and their background counterparts), since terminals at this
+    /// level commonly don't understand the 256 color sequences. At
+    /// `ColorLevel::None`, no colors are written at all, but styles (such as
+    /// bold) still are.
+    ///
+    /// [`ColorLevel::from_env`] can be used to guess the level of the
+    /// current terminal.
+    ///
+    /// By default, this is `ColorLevel::TrueColor`.
+    pub fn set_color_level(&mut self, level: ColorLevel) -> &mut Ansi<W> {
+        self.color_level = level;
+        self.last_spec = None;
+        self
+    }
+
+    /// Set the bytes written by `reset`.
+    ///
+    /// This is useful for consumers that expect a particular form of reset
+    /// (such as `\x1B[m`), or that want a reset to also clear other state
+    /// (such as an open hyperlink). The sequence is written as is, so callers
+    /// must ensure it's well formed.
+    ///
+    /// This only affects explicit calls to `reset`. The reset that
+    /// `set_color` writes before applying a spec is always `\x1B[0m`, since
+    /// it must only clear the color settings. Passing `None` restores the
+    /// default.
+    ///
+    /// By default, `reset` writes `\x1B[0m`. This is kept as the default,
+    /// rather than the shorter and equivalent `\x1B[m`, so that the output
+    /// of existing programs doesn't change.
+    pub fn set_reset_sequence(&mut self, seq: Option<&[u8]>) -> &mut Ansi<W> {
+        self.reset_sequence = seq.map(|seq| seq.to_vec());
+        self.last_spec = None;
+        self
+    }
+
+    /// When enabled, `reset` also closes a hyperlink if one is open.
+    ///
+    /// A hyperlink is considered open after a hyperlink is opened via
+    /// `set_hyperlink` or `set_hyperlink_with`, and until it is closed. When
+    /// this is enabled and a hyperlink is open, `reset` writes
+    /// `\x1B]8;;\x1B\\` after the reset sequence. Nothing extra is written
+    /// when no hyperlink is open. Note that hyperlinks written directly to
+    /// this writer (e.g., via `io::Write`) are not tracked.
+    ///
+    /// This is disabled by default, so that `reset` only resets colors.
+    pub fn set_reset_clears_hyperlink(&mut self, yes: bool) -> &mut Ansi<W> {
+        self.reset_clears_hyperlink = yes;
+        self
+    }
+
+    /// Erase part or all of the current line.
+    ///
+    /// Erasing does not move the cursor. For example, to overwrite the
+    /// current line, callers should erase it and then write a carriage
+    /// return before writing the new contents.
+    pub fn erase_line(&mut self, mode: EraseLineMode) -> io::Result<()> {
+        match mode {
+            EraseLineMode::ToEnd => self.write_str("\x1B[0K"),
+            EraseLineMode::ToStart => self.write_str("\x1B[1K"),
+            EraseLineMode::All => self.write_str("\x1B[2K"),
+        }
+    }
+
+    /// Erase the entire current line.
+    ///
+    /// This is an alias for `erase_line(EraseLineMode::All)`. Like
+    /// [`Ansi::erase_line`], it doesn't move the cursor.
+    pub fn clear_line(&mut self) -> io::Result<()> {
+        self.erase_line(EraseLineMode::All)
+    }
+
+    /// Erase part or all of the display.
+    ///
+    /// Erasing does not move the cursor. In particular, erasing the entire
+    /// display leaves the cursor where it was rather than moving it to the
+    /// top left corner.
+    pub fn erase_display(&mut self, mode: EraseDisplayMode) -> io::Result<()> {
+        match mode {
+            EraseDisplayMode::ToEnd => self.write_str("\x1B[0J"),
+            EraseDisplayMode::ToStart => self.write_str("\x1B[1J"),
+            EraseDisplayMode::All => self.write_str("\x1B[2J"),
+            EraseDisplayMode::AllAndScrollback => self.write_str("\x1B[3J"),
+        }
+    }
+
+    /// Save the current cursor position and attributes.
+    ///
+    /// The saved state is kept by the terminal and not by this writer. It
+    /// can be restored with [`Ansi::restore_cursor`]. Most terminals only
+    /// remember a single saved state, so saving again overwrites the
+    /// previously saved state.
+    ///
+    /// This uses the DEC sequence (`ESC 7`), which is more widely supported
+    /// than its ANSI counterpart.
+    ///
+    /// See [`SavedCursor`] for a guard that restores the cursor
+    /// automatically.
+    pub fn save_cursor(&mut self) -> io::Result<()> {
+        self.write_str("\x1B7")
+    }
+
+    /// Restore the cursor position and attributes most recently saved with
+    /// [`Ansi::save_cursor`].
+    ///
+    /// This uses the DEC sequence (`ESC 8`), which is more widely supported
+    /// than its ANSI counterpart.
+    pub fn restore_cursor(&mut self) -> io::Result<()> {
+        // This also restores the color settings that were saved.
+        self.last_spec = None;
+        self.write_str("\x1B8")
+    }
+
+    /// Move the cursor to the given row and column.
+    ///
+    /// Both `row` and `col` are 1-indexed, so that `move_cursor_to(1, 1)`
+    /// moves the cursor to the top left corner. An error is returned if
+    /// either is `0`, and nothing is written in that case.
+    ///
+    /// This uses the CUP sequence (`ESC [ row ; col H`).
+    pub fn move_cursor_to(&mut self, row: u16, col: u16) -> io::Result<()> {
+        if row == 0 || col == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cursor position must be 1-indexed, got {};{}",
+                    row, col
+                ),
+            ));
+        }
+        write!(self, "\x1B[{};{}H", row, col)
+    }
+
+    /// Set the shape of the cursor and whether it blinks.
+    ///
+    /// This uses DECSCUSR. Terminals that don't support it typically ignore
+    /// the sequence. The style can be restored to the user's default with
+    /// [`Ansi::reset_cursor_style`].
+    pub fn set_cursor_style(&mut self, style: CursorStyle) -> io::Result<()> {
+        match style {
+            CursorStyle::DefaultUserShape => self.write_str("\x1B[0 q"),
+            CursorStyle::BlinkingBlock => self.write_str("\x1B[1 q"),
+            CursorStyle::SteadyBlock => self.write_str("\x1B[2 q"),
+            CursorStyle::BlinkingUnderline => self.write_str("\x1B[3 q"),
+            CursorStyle::SteadyUnderline => self.write_str("\x1B[4 q"),
+            CursorStyle::BlinkingBar => self.write_str("\x1B[5 q"),
+            CursorStyle::SteadyBar => self.write_str("\x1B[6 q"),
+        }
+    }
+
+    /// Restore the cursor style configured by the user.
+    ///
+    /// This is equivalent to setting the style to
+    /// `CursorStyle::DefaultUserShape`.
+    pub fn reset_cursor_style(&mut self) -> io::Result<()> {
+        self.set_cursor_style(CursorStyle::DefaultUserShape)
+    }
+
+    /// Show or hide the cursor.
+    ///
+    /// Terminal UIs commonly hide the cursor while rendering to prevent it
+    /// from flickering. See [`HiddenCursor`] for a guard that shows the
+    /// cursor again automatically.
+    pub fn set_cursor_visibility(&mut self, visible: bool) -> io::Result<()> {
+        if visible {
+            self.write_str("\x1B[?25h")
+        } else {
+            self.write_str("\x1B[?25l")
+        }
+    }
+
+    /// Set both the icon name and the window title of the terminal.
+    ///
+    /// This uses OSC 0 and terminates the sequence with ST (`ESC \\`) rather
+    /// than BEL, since ST is handled more consistently by terminal
+    /// multiplexers. The title is written as UTF-8.
+    ///
+    /// An error is returned if the title contains a control character, such
+    /// as `ESC` or `BEL`, since it could end the sequence early. Nothing is
+    /// written when an error is returned.
+    pub fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.write_osc_title("0", title)
+    }
+
+    /// Set only the window title of the terminal, leaving the icon name as
+    /// is.
+    ///
+    /// This is like [`Ansi::set_title`], except it uses OSC 2.
+    pub fn set_window_title(&mut self, title: &str) -> io::Result<()> {
+        self.write_osc_title("2", title)
+    }
+
+    /// Switch to the terminal's alternate screen buffer.
+    ///
+    /// This saves the cursor position and switches to a separate screen, as
+    /// is typical for full-screen applications. The original screen is shown
+    /// again by [`Ansi::alternate_screen_exit`]. See [`AlternateScreen`] for
+    /// a guard that exits automatically.
+    pub fn alternate_screen_enter(&mut self) -> io::Result<()> {
+        self.last_spec = None;
+        self.write_str("\x1B[?1049h")
+    }
+
+    /// Switch back from the terminal's alternate screen buffer.
+    ///
+    /// This restores the screen and cursor position as they were when
+    /// [`Ansi::alternate_screen_enter`] was called.
+    pub fn alternate_screen_exit(&mut self) -> io::Result<()> {
+        self.last_spec = None;
+        self.write_str("\x1B[?1049l")
+    }
+
+    /// Begin a synchronized update.
+    ///
+    /// Terminals that support synchronized output (DEC private mode 2026)
+    /// stop redrawing the screen until [`Ansi::end_synchronized_output`] is
+    /// called, and then show all of the output written in between at once.
+    /// This prevents flickering when an update is made in several steps.
+    /// Terminals that don't support this mode simply ignore the sequence.
+    /// See [`SynchronizedUpdate`] for a guard that ends the update
+    /// automatically.
+    pub fn begin_synchronized_output(&mut self) -> io::Result<()> {
+        self.write_str("\x1B[?2026h")
+    }
+
+    /// End a synchronized update started by
+    /// [`Ansi::begin_synchronized_output`].
+    ///
+    /// Terminals that don't support synchronized output simply ignore this
+    /// sequence.
+    pub fn end_synchronized_output(&mut self) -> io::Result<()> {
+        self.write_str("\x1B[?2026l")
+    }
+
+    /// Redefine the RGB value of a color in the terminal's 256 color
+    /// palette.
+    ///
+    /// Note that this modifies the terminal's palette itself, and not just
+    /// the output of this writer. Every use of the color in the terminal is
+    /// affected, including text that has already been written, and the
+    /// change usually outlives this process. Use
+    /// [`Ansi::reset_xterm_color`] to restore the color.
+    ///
+    /// This uses OSC 4 and terminates the sequence with ST (`ESC \\`).
+    pub fn define_xterm_color(
+        &mut self,
+        index: u8,
+        r: u8,
+        g: u8,
+        b: u8,
+    ) -> io::Result<()> {
+        write!(
+            self,
+            "\x1B]4;{};rgb:{:02x}/{:02x}/{:02x}\x1B\\",
+            index, r, g, b
+        )
+    }
+
+    /// Restore a color in the terminal's 256 color palette to its default
+    /// value, undoing [`Ansi::define_xterm_color`].
+    ///
+    /// This uses OSC 104 and terminates the sequence with ST (`ESC \\`).
+    pub fn reset_xterm_color(&mut self, index: u8) -> io::Result<()> {
+        write!(self, "\x1B]104;{}\x1B\\", index)
+    }
+}
+
+impl<W: io::Write> io::Write for Ansi<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.wtr.write(buf)
+    }
+
+    // Adding this method here is not required because it has a default impl,
+    // but it seems to provide a perf improvement in some cases when using
+    // a `BufWriter` with lots of writes.
+    //
+    // See https://github.com/BurntSushi/termcolor/pull/56 for more details
+    // and a minimized example.
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.wtr.write_all(buf)
+    }
 
     #[inline]
     fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+        self.wtr.flush()
     }
 }
 
@@ -1514,10 +3139,145 @@ impl<W: io::Write> WriteColor for Ansi<W> {
         true
     }
 
+    /// Set the color settings of this writer.
+    ///
+    /// If [`Ansi::set_dedup_colors`] is enabled and the given settings are
+    /// identical to the settings most recently applied, then nothing is
+    /// written, since the terminal is already in the requested state.
     #[inline]
     fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.try_set_color(spec).map(|_| ())
+    }
+
+    /// Set the color settings of this writer.
+    ///
+    /// If [`Ansi::set_dedup_colors`] is enabled, then nothing is written and
+    /// `false` is returned when the settings are identical to the settings
+    /// most recently applied by `set_color` or `try_set_color`.
+    #[inline]
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        if self.dedup_colors && self.last_spec.as_ref() == Some(spec) {
+            return Ok(false);
+        }
+        self.write_spec(spec)?;
+        self.last_spec = Some(spec.clone());
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
+        self.write_str("\x1B]8;;")?;
+        if let Some(uri) = link.uri() {
+            self.write_all(&sanitize_hyperlink_uri(uri))?;
+        }
+        self.write_str("\x1B\\")?;
+        self.hyperlink_open = link.uri().is_some();
+        Ok(())
+    }
+
+    /// Open a hyperlink with the given parameters.
+    ///
+    /// Each parameter is written as `key=value`, and parameters are
+    /// separated by `:`. Since `:` and `;` delimit the parameters, an error
+    /// is returned if a key or value contains either of them, if a key is
+    /// empty or contains `=`, or if either contains a control character.
+    /// Nothing is written when an error is returned.
+    #[inline]
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        for &(key, value) in params {
+            let invalid = |c: char| c == ':' || c == ';' || c.is_control();
+            if key.is_empty()
+                || key.contains(|c| invalid(c) || c == '=')
+                || value.contains(invalid)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid hyperlink parameter: {}={}", key, value),
+                ));
+            }
+        }
+        self.write_str("\x1B]8;")?;
+        for (i, &(key, value)) in params.iter().enumerate() {
+            if i > 0 {
+                self.write_str(":")?;
+            }
+            self.write_str(key)?;
+            self.write_str("=")?;
+            self.write_str(value)?;
+        }
+        self.write_str(";")?;
+        self.write_all(&sanitize_hyperlink_uri(uri))?;
+        self.write_str("\x1B\\")?;
+        self.hyperlink_open = true;
+        Ok(())
+    }
+
+    #[inline]
+    fn reset(&mut self) -> io::Result<()> {
+        self.last_spec = None;
+        match self.reset_sequence {
+            None => self.write_reset()?,
+            Some(ref seq) => self.wtr.write_all(seq)?,
+        }
+        if self.reset_clears_hyperlink && self.hyperlink_open {
+            self.write_str("\x1B]8;;\x1B\\")?;
+            self.hyperlink_open = false;
+        }
+        if self.flush_on_reset {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn is_synchronous(&self) -> bool {
+        false
+    }
+}
+
+impl<W: io::Write> Ansi<W> {
+    /// Create a new `Ansi` writer around the given writer with the same
+    /// settings as this one.
+    fn wrap<Q: io::Write>(&self, wtr: Q) -> Ansi<Q> {
+        Ansi {
+            wtr,
+            flush_on_reset: self.flush_on_reset,
+            palette: self.palette,
+            color_level: self.color_level,
+            // The wrapped writer doesn't share the color settings tracked by
+            // this writer, so it can't skip any of them either.
+            dedup_colors: false,
+            last_spec: None,
+            reset_sequence: self.reset_sequence.clone(),
+            reset_clears_hyperlink: self.reset_clears_hyperlink,
+            hyperlink_open: self.hyperlink_open,
+        }
+    }
+
+    /// Like `wrap`, but consumes this writer and builds the new writer by
+    /// applying `f` to the underlying writer.
+    fn map<Q: io::Write>(self, f: impl FnOnce(W) -> Q) -> Ansi<Q> {
+        Ansi {
+            wtr: f(self.wtr),
+            flush_on_reset: self.flush_on_reset,
+            palette: self.palette,
+            color_level: self.color_level,
+            dedup_colors: self.dedup_colors,
+            last_spec: None,
+            reset_sequence: self.reset_sequence,
+            reset_clears_hyperlink: self.reset_clears_hyperlink,
+            hyperlink_open: self.hyperlink_open,
+        }
+    }
+
+    /// Write the escape sequences for the given color settings.
+    fn write_spec(&mut self, spec: &ColorSpec) -> io::Result<()> {
         if spec.reset {
-            self.reset()?;
+            self.write_reset()?;
         }
         if spec.bold {
             self.write_str("\x1B[1m")?;
@@ -1529,7 +3289,7 @@ impl<W: io::Write> WriteColor for Ansi<W> {
             self.write_str("\x1B[3m")?;
         }
         if spec.underline {
-            self.write_str("\x1B[4m")?;
+            self.write_underline(spec.underline_style)?;
         }
         if spec.strikethrough {
             self.write_str("\x1B[9m")?;
@@ -1543,31 +3303,108 @@ impl<W: io::Write> WriteColor for Ansi<W> {
         Ok(())
     }
 
-    #[inline]
-    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
-        self.write_str("\x1B]8;;")?;
-        if let Some(uri) = link.uri() {
-            self.write_all(uri)?;
+    /// Write only the escape sequences needed to change the terminal from
+    /// the `prev` color settings to the `next` color settings.
+    ///
+    /// See [`ColorSpec::diff_ansi`] for the precise rules.
+    fn write_spec_diff(
+        &mut self,
+        prev: &ColorSpec,
+        next: &ColorSpec,
+    ) -> io::Result<()> {
+        // Bold and dimmed are both turned off by the same code, so turning
+        // off either one requires turning the other back on if it's kept.
+        if (prev.bold && !next.bold) || (prev.dimmed && !next.dimmed) {
+            self.write_str("\x1B[22m")?;
+            if next.bold {
+                self.write_str("\x1B[1m")?;
+            }
+            if next.dimmed {
+                self.write_str("\x1B[2m")?;
+            }
+        } else {
+            if next.bold && !prev.bold {
+                self.write_str("\x1B[1m")?;
+            }
+            if next.dimmed && !prev.dimmed {
+                self.write_str("\x1B[2m")?;
+            }
         }
-        self.write_str("\x1B\\")
+        if prev.italic != next.italic {
+            self.write_str(if next.italic { "\x1B[3m" } else { "\x1B[23m" })?;
+        }
+        if !next.underline {
+            if prev.underline {
+                self.write_str("\x1B[24m")?;
+            }
+        } else if !prev.underline
+            || prev.underline_style != next.underline_style
+        {
+            self.write_underline(next.underline_style)?;
+        }
+        if prev.strikethrough != next.strikethrough {
+            self.write_str(if next.strikethrough {
+                "\x1B[9m"
+            } else {
+                "\x1B[29m"
+            })?;
+        }
+        // The intense setting changes how colors are written, so colors are
+        // rewritten when it changes too.
+        let intense_changed = prev.intense != next.intense;
+        if prev.fg_color != next.fg_color
+            || (intense_changed && next.fg_color.is_some())
+        {
+            match next.fg_color {
+                None => self.write_str("\x1B[39m")?,
+                Some(ref c) => self.write_color(true, c, next.intense)?,
+            }
+        }
+        if prev.bg_color != next.bg_color
+            || (intense_changed && next.bg_color.is_some())
+        {
+            match next.bg_color {
+                None => self.write_str("\x1B[49m")?,
+                Some(ref c) => self.write_color(false, c, next.intense)?,
+            }
+        }
+        Ok(())
     }
 
-    #[inline]
-    fn reset(&mut self) -> io::Result<()> {
-        self.write_str("\x1B[0m")
+    fn write_underline(&mut self, style: UnderlineStyle) -> io::Result<()> {
+        match style {
+            UnderlineStyle::Straight => self.write_str("\x1B[4m"),
+            UnderlineStyle::Double => self.write_str("\x1B[4:2m"),
+            UnderlineStyle::Curly => self.write_str("\x1B[4:3m"),
+            UnderlineStyle::Dotted => self.write_str("\x1B[4:4m"),
+            UnderlineStyle::Dashed => self.write_str("\x1B[4:5m"),
+        }
     }
 
-    #[inline]
-    fn is_synchronous(&self) -> bool {
-        false
+    fn write_osc_title(&mut self, code: &str, title: &str) -> io::Result<()> {
+        // A control character (such as ESC or BEL) could terminate the
+        // sequence early and inject arbitrary escape sequences.
+        if title.contains(char::is_control) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid terminal title: {:?}", title),
+            ));
+        }
+        self.write_str("\x1B]")?;
+        self.write_str(code)?;
+        self.write_str(";")?;
+        self.write_str(title)?;
+        self.write_str("\x1B\\")
     }
-}
 
-impl<W: io::Write> Ansi<W> {
     fn write_str(&mut self, s: &str) -> io::Result<()> {
         self.write_all(s.as_bytes())
     }
 
+    fn write_reset(&mut self) -> io::Result<()> {
+        self.write_str("\x1B[0m")
+    }
+
     fn write_color(
         &mut self,
         fg: bool,
@@ -1646,8 +3483,16 @@ impl<W: io::Write> Ansi<W> {
                 }
             }};
         }
+        let c = self.palette.and_then(|p| p.get(*c)).unwrap_or(*c);
+        if self.color_level == ColorLevel::Basic {
+            return self.write_basic_color(fg, c, intense);
+        }
+        let c = match c.to_level(self.color_level) {
+            None => return Ok(()),
+            Some(c) => c,
+        };
         if intense {
-            match *c {
+            match c {
                 Color::Black => write_intense!("8"),
                 Color::Blue => write_intense!("12"),
                 Color::Green => write_intense!("10"),
@@ -1658,10 +3503,10 @@ impl<W: io::Write> Ansi<W> {
                 Color::White => write_intense!("15"),
                 Color::Ansi256(c) => write_custom!(c),
                 Color::Rgb(r, g, b) => write_custom!(r, g, b),
-                Color::__Nonexhaustive => unreachable!(),
+                Color::Default => write_normal!("9"),
             }
         } else {
-            match *c {
+            match c {
                 Color::Black => write_normal!("0"),
                 Color::Blue => write_normal!("4"),
                 Color::Green => write_normal!("2"),
@@ -1672,901 +3517,5947 @@ impl<W: io::Write> Ansi<W> {
                 Color::White => write_normal!("7"),
                 Color::Ansi256(c) => write_custom!(c),
                 Color::Rgb(r, g, b) => write_custom!(r, g, b),
-                Color::__Nonexhaustive => unreachable!(),
+                Color::Default => write_normal!("9"),
+            }
+        }
+    }
+
+    /// Write the given color using only the 16 basic color codes.
+    fn write_basic_color(
+        &mut self,
+        fg: bool,
+        c: Color,
+        intense: bool,
+    ) -> io::Result<()> {
+        // This is always less than 16 for one of the basic colors.
+        let mut n = match c.nearest_16().nearest_256_index() {
+            Some(n) => n,
+            None => {
+                return self.write_str(if fg {
+                    "\x1B[39m"
+                } else {
+                    "\x1B[49m"
+                });
             }
+        };
+        if intense && n < 8 {
+            n += 8;
         }
+        let code = match (fg, n < 8) {
+            (true, true) => 30 + n,
+            (true, false) => 90 + n - 8,
+            (false, true) => 40 + n,
+            (false, false) => 100 + n - 8,
+        };
+        write!(self, "\x1B[{}m", code)
     }
 }
 
-impl WriteColor for io::Sink {
-    fn supports_color(&self) -> bool {
-        false
+/// Satisfies `WriteColor` by writing HTML.
+///
+/// Text written to this writer is HTML escaped, and colors are expressed by
+/// wrapping text in `<span>` elements with inline CSS. This is useful for
+/// rendering colored output in a browser.
+///
+/// Setting a color specification with `reset` enabled (the default) closes
+/// any spans that are open before opening a new one, while a specification
+/// with `reset` disabled opens a nested span. Calling `reset` closes all
+/// open spans, so callers should call it once they're done writing.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Write;
+/// use termcolor::{Color, ColorSpec, Html, WriteColor};
+///
+/// let mut wtr = Html::new(vec![]);
+/// wtr.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
+/// write!(wtr, "a < b")?;
+/// wtr.reset()?;
+/// assert_eq!(
+///     wtr.into_inner(),
+///     b"<span style=\"color:#cd0000;font-weight:bold\">a &lt; b</span>",
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Html<W> {
+    wtr: W,
+    open_spans: usize,
+}
+
+impl<W: Write> Html<W> {
+    /// Create a new writer that satisfies `WriteColor` by writing HTML.
+    pub fn new(wtr: W) -> Html<W> {
+        Html { wtr, open_spans: 0 }
     }
 
-    fn supports_hyperlinks(&self) -> bool {
-        false
+    /// Consume this `Html` value and return the inner writer.
+    ///
+    /// Note that this doesn't close any open spans.
+    pub fn into_inner(self) -> W {
+        self.wtr
     }
 
-    fn set_color(&mut self, _: &ColorSpec) -> io::Result<()> {
-        Ok(())
+    /// Return a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.wtr
     }
 
-    fn set_hyperlink(&mut self, _: &HyperlinkSpec) -> io::Result<()> {
-        Ok(())
+    /// Return a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.wtr
     }
 
-    fn reset(&mut self) -> io::Result<()> {
+    fn close_spans(&mut self) -> io::Result<()> {
+        while self.open_spans > 0 {
+            self.wtr.write_all(b"</span>")?;
+            self.open_spans -= 1;
+        }
         Ok(())
     }
 }
 
-/// An in-memory buffer that provides Windows console coloring.
-///
-/// This doesn't actually communicate with the Windows console. Instead, it
-/// acts like a normal buffer but also saves the color information associated
-/// with positions in the buffer. It is only when the buffer is written to the
-/// console that coloring is actually applied.
-///
-/// This is roughly isomorphic to the ANSI based approach (i.e.,
-/// `Ansi<Vec<u8>>`), except with ANSI, the color information is embedded
-/// directly into the buffer.
-///
-/// Note that there is no way to write something generic like
-/// `WindowsConsole<W: io::Write>` since coloring on Windows is tied
-/// specifically to the console APIs, and therefore can't work on arbitrary
-/// writers.
-#[cfg(windows)]
-#[derive(Clone, Debug)]
-struct WindowsBuffer {
-    /// The actual content that should be printed.
-    buf: Vec<u8>,
-    /// A sequence of position oriented color specifications. Namely, each
-    /// element is a position and a color spec, where the color spec should
-    /// be applied at the position inside of `buf`.
-    ///
-    /// A missing color spec implies the underlying console should be reset.
-    colors: Vec<(usize, Option<ColorSpec>)>,
+impl<W: io::Write> io::Write for Html<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut last = 0;
+        for (i, &b) in buf.iter().enumerate() {
+            let escaped: &[u8] = match b {
+                b'&' => b"&amp;",
+                b'<' => b"&lt;",
+                b'>' => b"&gt;",
+                b'"' => b"&quot;",
+                b'\'' => b"&#39;",
+                _ => continue,
+            };
+            self.wtr.write_all(&buf[last..i])?;
+            self.wtr.write_all(escaped)?;
+            last = i + 1;
+        }
+        self.wtr.write_all(&buf[last..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
 }
 
-#[cfg(windows)]
-impl WindowsBuffer {
-    /// Create a new empty buffer for Windows console coloring.
-    fn new() -> WindowsBuffer {
-        WindowsBuffer { buf: vec![], colors: vec![] }
+impl<W: io::Write> WriteColor for Html<W> {
+    fn supports_color(&self) -> bool {
+        true
     }
 
-    /// Push the given color specification into this buffer.
-    ///
-    /// This has the effect of setting the given color information at the
-    /// current position in the buffer.
-    fn push(&mut self, spec: Option<ColorSpec>) {
-        let pos = self.buf.len();
-        self.colors.push((pos, spec));
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        if spec.reset {
+            self.close_spans()?;
+        }
+        let mut styles = vec![];
+        if let Some(css) = spec.fg_color.and_then(|c| c.to_css(spec.intense)) {
+            styles.push(format!("color:{}", css));
+        }
+        if let Some(css) = spec.bg_color.and_then(|c| c.to_css(spec.intense)) {
+            styles.push(format!("background-color:{}", css));
+        }
+        if spec.bold {
+            styles.push("font-weight:bold".to_string());
+        }
+        if spec.dimmed {
+            styles.push("opacity:0.5".to_string());
+        }
+        if spec.italic {
+            styles.push("font-style:italic".to_string());
+        }
+        match (spec.underline, spec.strikethrough) {
+            (true, true) => {
+                styles.push("text-decoration:underline line-through".into())
+            }
+            (true, false) => styles.push("text-decoration:underline".into()),
+            (false, true) => {
+                styles.push("text-decoration:line-through".into())
+            }
+            (false, false) => {}
+        }
+        if spec.underline {
+            let css = match spec.underline_style {
+                UnderlineStyle::Straight => None,
+                UnderlineStyle::Double => Some("double"),
+                UnderlineStyle::Curly => Some("wavy"),
+                UnderlineStyle::Dotted => Some("dotted"),
+                UnderlineStyle::Dashed => Some("dashed"),
+            };
+            if let Some(css) = css {
+                styles.push(format!("text-decoration-style:{}", css));
+            }
+        }
+        if styles.is_empty() {
+            return Ok(());
+        }
+        write!(self.wtr, "<span style=\"{}\">", styles.join(";"))?;
+        self.open_spans += 1;
+        Ok(())
     }
 
-    /// Print the contents to the given stream handle, and use the console
-    /// for coloring.
-    fn print(
-        &self,
-        console: &mut wincon::Console,
-        stream: &mut LossyStandardStream<IoStandardStreamLock>,
-    ) -> io::Result<()> {
-        let mut last = 0;
-        for &(pos, ref spec) in &self.colors {
-            stream.write_all(&self.buf[last..pos])?;
-            stream.flush()?;
-            last = pos;
-            match *spec {
-                None => console.reset()?,
-                Some(ref spec) => spec.write_console(console)?,
+    fn reset(&mut self) -> io::Result<()> {
+        self.close_spans()
+    }
+}
+
+/// Defines a guard that borrows an [`Ansi`] writer, calls one of its methods
+/// when created and another one when dropped.
+///
+/// Every guard dereferences to the writer, so output can be written while
+/// the guard is alive. After calling the method on drop, the guard flushes
+/// the writer so that the sequence reaches the terminal even when writing
+/// through a buffered writer. Errors that occur on drop are ignored.
+macro_rules! ansi_guard {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident;
+
+        $(#[$new_attr:meta])*
+        new => $enter:ident($($enter_arg:expr),*);
+        drop => $exit:ident($($exit_arg:expr),*);
+    ) => {
+        $(#[$attr])*
+        ///
+        /// This dereferences to the underlying [`Ansi`] writer. Dropping the
+        #[doc = concat!(
+            "guard calls [`Ansi::", stringify!($exit), "`] and then flushes ",
+            "the writer, ignoring any error."
+        )]
+        #[derive(Debug)]
+        pub struct $name<'a, W: io::Write> {
+            wtr: &'a mut Ansi<W>,
+        }
+
+        impl<'a, W: io::Write> $name<'a, W> {
+            $(#[$new_attr])*
+            pub fn new(wtr: &'a mut Ansi<W>) -> io::Result<$name<'a, W>> {
+                wtr.$enter($($enter_arg),*)?;
+                Ok($name { wtr })
             }
         }
-        stream.write_all(&self.buf[last..])?;
-        stream.flush()
+
+        impl<'a, W: io::Write> Deref for $name<'a, W> {
+            type Target = Ansi<W>;
+
+            fn deref(&self) -> &Ansi<W> {
+                self.wtr
+            }
+        }
+
+        impl<'a, W: io::Write> DerefMut for $name<'a, W> {
+            fn deref_mut(&mut self) -> &mut Ansi<W> {
+                self.wtr
+            }
+        }
+
+        impl<'a, W: io::Write> Drop for $name<'a, W> {
+            fn drop(&mut self) {
+                let _ = self.wtr.$exit($($exit_arg),*);
+                let _ = self.wtr.flush();
+            }
+        }
+    };
+}
+
+ansi_guard! {
+    /// A guard that saves the cursor when created and restores it when
+    /// dropped.
+    ///
+    /// This is useful for drawing somewhere else on the screen, such as a
+    /// status line, and then returning to where the cursor was. The saved
+    /// state lives in the terminal, and most terminals only remember one
+    /// saved state, so nesting these guards restores the cursor saved by
+    /// the innermost guard.
+    pub struct SavedCursor;
+
+    /// Save the cursor of the given writer via [`Ansi::save_cursor`].
+    ///
+    /// The cursor is restored via [`Ansi::restore_cursor`] when the guard
+    /// returned is dropped.
+    new => save_cursor();
+    drop => restore_cursor();
+}
+
+ansi_guard! {
+    /// A guard that enters the alternate screen when created and exits it when
+    /// dropped.
+    ///
+    /// Full-screen programs draw on the alternate screen so that the
+    /// terminal's scrollback is left untouched. With this guard, the
+    /// original screen comes back even if drawing returns early or panics.
+    pub struct AlternateScreen;
+
+    /// Enter the alternate screen via [`Ansi::alternate_screen_enter`].
+    ///
+    /// The alternate screen is exited via [`Ansi::alternate_screen_exit`]
+    /// when the guard returned is dropped.
+    new => alternate_screen_enter();
+    drop => alternate_screen_exit();
+}
+
+ansi_guard! {
+    /// A guard that hides the cursor when created and shows it again when
+    /// dropped.
+    ///
+    /// Hiding the cursor while redrawing prevents it from flickering across
+    /// the screen. The cursor is always shown on drop, even if it was already
+    /// hidden when the guard was created, so a program that panics or returns
+    /// early doesn't leave the user without a cursor.
+    pub struct HiddenCursor;
+
+    /// Hide the cursor via [`Ansi::set_cursor_visibility`].
+    ///
+    /// The cursor is shown again when the guard returned is dropped.
+    new => set_cursor_visibility(false);
+    drop => set_cursor_visibility(true);
+}
+
+ansi_guard! {
+    /// A guard that begins a synchronized update when created and ends it when
+    /// dropped.
+    ///
+    /// See [`Ansi::begin_synchronized_output`] for what a synchronized update
+    /// does. Since the update is ended and flushed on drop, the terminal
+    /// receives the entire update at once and resumes redrawing afterwards.
+    pub struct SynchronizedUpdate;
+
+    /// Begin a synchronized update via
+    /// [`Ansi::begin_synchronized_output`].
+    ///
+    /// The update is ended via [`Ansi::end_synchronized_output`] when the
+    /// guard returned is dropped.
+    new => begin_synchronized_output();
+    drop => end_synchronized_output();
+}
+
+/// Applies a color specification to every line written to a writer.
+///
+/// The color specification is set at the start of each line and the colors
+/// are reset just before each line terminator, so that callers can write
+/// plain text (for example, with `write!`) and have each line colored. Lines
+/// may be split across any number of writes.
+///
+/// If the last line written has no line terminator, then its colors remain
+/// set until either another line terminator is written or `reset` is called.
+/// All other `WriteColor` methods are forwarded to the inner writer.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Write;
+/// use termcolor::{Ansi, Color, ColorSpec, LineColor};
+///
+/// let mut spec = ColorSpec::new();
+/// spec.set_fg(Some(Color::Green));
+/// let mut wtr = LineColor::new(Ansi::new(vec![]), spec);
+/// write!(wtr, "foo\nbar\n")?;
+/// assert_eq!(
+///     wtr.into_inner().into_inner(),
+///     b"\x1B[0m\x1B[32mfoo\x1B[0m\n\x1B[0m\x1B[32mbar\x1B[0m\n",
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct LineColor<W> {
+    wtr: W,
+    spec: ColorSpec,
+    in_line: bool,
+}
+
+impl<W: WriteColor> LineColor<W> {
+    /// Create a new writer that colors each line written to `wtr` with the
+    /// given color specification.
+    pub fn new(wtr: W, spec: ColorSpec) -> LineColor<W> {
+        LineColor { wtr, spec, in_line: false }
     }
 
-    /// Clear the buffer.
-    fn clear(&mut self) {
-        self.buf.clear();
-        self.colors.clear();
+    /// Consume this `LineColor` value and return the inner writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+
+    /// Return a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.wtr
+    }
+
+    /// Return a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.wtr
     }
 }
 
-#[cfg(windows)]
-impl io::Write for WindowsBuffer {
-    #[inline]
+impl<W: WriteColor> io::Write for LineColor<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.buf.extend_from_slice(buf);
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let (line, terminated) =
+                match rest.iter().position(|&b| b == b'\n') {
+                    Some(i) => (&rest[..i], true),
+                    None => (rest, false),
+                };
+            if !line.is_empty() {
+                if !self.in_line {
+                    self.wtr.set_color(&self.spec)?;
+                    self.in_line = true;
+                }
+                self.wtr.write_all(line)?;
+            }
+            if !terminated {
+                break;
+            }
+            if self.in_line {
+                self.wtr.reset()?;
+                self.in_line = false;
+            }
+            self.wtr.write_all(b"\n")?;
+            rest = &rest[line.len() + 1..];
+        }
         Ok(buf.len())
     }
 
-    #[inline]
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        self.wtr.flush()
     }
 }
 
-#[cfg(windows)]
-impl WriteColor for WindowsBuffer {
-    #[inline]
+impl<W: WriteColor> WriteColor for LineColor<W> {
     fn supports_color(&self) -> bool {
-        true
+        self.wtr.supports_color()
     }
 
-    #[inline]
     fn supports_hyperlinks(&self) -> bool {
-        false
+        self.wtr.supports_hyperlinks()
     }
 
-    #[inline]
     fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
-        self.push(Some(spec.clone()));
-        Ok(())
+        self.wtr.set_color(spec)
     }
 
-    #[inline]
-    fn set_hyperlink(&mut self, _: &HyperlinkSpec) -> io::Result<()> {
-        Ok(())
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        self.wtr.try_set_color(spec)
+    }
+
+    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
+        self.wtr.set_hyperlink(link)
+    }
+
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.wtr.set_hyperlink_with(params, uri)
     }
 
-    #[inline]
     fn reset(&mut self) -> io::Result<()> {
-        self.push(None);
-        Ok(())
+        self.in_line = false;
+        self.wtr.reset()
     }
 
-    #[inline]
     fn is_synchronous(&self) -> bool {
-        false
+        self.wtr.is_synchronous()
     }
 }
 
-/// A color specification.
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct ColorSpec {
-    fg_color: Option<Color>,
-    bg_color: Option<Color>,
-    bold: bool,
-    intense: bool,
-    underline: bool,
-    dimmed: bool,
-    italic: bool,
-    reset: bool,
-    strikethrough: bool,
+/// Prefixes every line written to a writer with an indent.
+///
+/// The indent is written just before the first byte of each line, so lines
+/// may be split across any number of writes and empty lines are left
+/// without trailing whitespace. If a color specification is set for the
+/// indent via [`Indent::set_indent_color`], then the indent is written in
+/// that color. Either way, the colors of the text being indented are reset
+/// around the indent and then restored, so the indent never takes on the
+/// colors of the text and the text keeps its own colors across lines.
+///
+/// Since `Indent` itself implements `WriteColor`, indents can be nested by
+/// wrapping one `Indent` in another.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Write;
+/// use termcolor::{Ansi, Color, ColorSpec, Indent, WriteColor};
+///
+/// let mut wtr = Indent::new(Ansi::new(vec![]), "  ");
+/// wtr.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+/// write!(wtr, "foo\nbar")?;
+/// wtr.reset()?;
+/// assert_eq!(
+///     wtr.into_inner().into_inner(),
+///     b"\x1B[0m\x1B[31m\x1B[0m  \x1B[0m\x1B[31mfoo\n\
+///       \x1B[0m  \x1B[0m\x1B[31mbar\x1B[0m",
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Indent<W> {
+    wtr: W,
+    indent: String,
+    indent_spec: Option<ColorSpec>,
+    spec: Option<ColorSpec>,
+    at_line_start: bool,
 }
 
-impl Default for ColorSpec {
-    fn default() -> ColorSpec {
-        ColorSpec {
-            fg_color: None,
-            bg_color: None,
-            bold: false,
-            intense: false,
-            underline: false,
-            dimmed: false,
-            italic: false,
-            reset: true,
-            strikethrough: false,
+impl<W: WriteColor> Indent<W> {
+    /// Create a new writer that prefixes each line written to `wtr` with
+    /// the given indent.
+    pub fn new(wtr: W, indent: &str) -> Indent<W> {
+        Indent {
+            wtr,
+            indent: indent.to_string(),
+            indent_spec: None,
+            spec: None,
+            at_line_start: true,
         }
     }
-}
 
-impl ColorSpec {
-    /// Create a new color specification that has no colors or styles.
-    pub fn new() -> ColorSpec {
-        ColorSpec::default()
+    /// Set the color specification used to write the indent.
+    ///
+    /// When this is `None`, the indent is written without any colors.
+    ///
+    /// By default, this is `None`.
+    pub fn set_indent_color(
+        &mut self,
+        spec: Option<ColorSpec>,
+    ) -> &mut Indent<W> {
+        self.indent_spec = spec;
+        self
     }
 
-    /// Get the foreground color.
-    pub fn fg(&self) -> Option<&Color> {
-        self.fg_color.as_ref()
+    /// Consume this `Indent` value and return the inner writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
     }
 
-    /// Set the foreground color.
-    pub fn set_fg(&mut self, color: Option<Color>) -> &mut ColorSpec {
-        self.fg_color = color;
-        self
+    /// Return a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.wtr
     }
 
-    /// Get the background color.
-    pub fn bg(&self) -> Option<&Color> {
-        self.bg_color.as_ref()
+    /// Return a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.wtr
     }
 
-    /// Set the background color.
-    pub fn set_bg(&mut self, color: Option<Color>) -> &mut ColorSpec {
-        self.bg_color = color;
-        self
+    fn write_indent(&mut self) -> io::Result<()> {
+        if self.indent_spec.is_none() && self.spec.is_none() {
+            return self.wtr.write_all(self.indent.as_bytes());
+        }
+        match self.indent_spec {
+            None => self.wtr.reset()?,
+            Some(ref spec) => self.wtr.set_color(spec)?,
+        }
+        self.wtr.write_all(self.indent.as_bytes())?;
+        match self.spec {
+            None => self.wtr.reset(),
+            Some(ref spec) => self.wtr.set_color(spec),
+        }
     }
+}
 
-    /// Get whether this is bold or not.
-    ///
-    /// Note that the bold setting has no effect in a Windows console.
-    pub fn bold(&self) -> bool {
-        self.bold
+impl<W: WriteColor> io::Write for Indent<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if self.at_line_start && rest[0] != b'\n' {
+                self.write_indent()?;
+                self.at_line_start = false;
+            }
+            match rest.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    self.wtr.write_all(&rest[..=i])?;
+                    self.at_line_start = true;
+                    rest = &rest[i + 1..];
+                }
+                None => {
+                    self.wtr.write_all(rest)?;
+                    break;
+                }
+            }
+        }
+        Ok(buf.len())
     }
 
-    /// Set whether the text is bolded or not.
-    ///
-    /// Note that the bold setting has no effect in a Windows console.
-    pub fn set_bold(&mut self, yes: bool) -> &mut ColorSpec {
-        self.bold = yes;
-        self
+    fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
     }
+}
 
-    /// Get whether this is dimmed or not.
-    ///
-    /// Note that the dimmed setting has no effect in a Windows console.
-    pub fn dimmed(&self) -> bool {
-        self.dimmed
+impl<W: WriteColor> WriteColor for Indent<W> {
+    fn supports_color(&self) -> bool {
+        self.wtr.supports_color()
     }
 
-    /// Set whether the text is dimmed or not.
-    ///
-    /// Note that the dimmed setting has no effect in a Windows console.
-    pub fn set_dimmed(&mut self, yes: bool) -> &mut ColorSpec {
-        self.dimmed = yes;
-        self
+    fn supports_hyperlinks(&self) -> bool {
+        self.wtr.supports_hyperlinks()
     }
 
-    /// Get whether this is italic or not.
-    ///
-    /// Note that the italic setting has no effect in a Windows console.
-    pub fn italic(&self) -> bool {
-        self.italic
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.spec = Some(spec.clone());
+        self.wtr.set_color(spec)
     }
 
-    /// Set whether the text is italicized or not.
-    ///
-    /// Note that the italic setting has no effect in a Windows console.
-    pub fn set_italic(&mut self, yes: bool) -> &mut ColorSpec {
-        self.italic = yes;
-        self
+    fn try_set_color(&mut self, spec: &ColorSpec) -> io::Result<bool> {
+        self.spec = Some(spec.clone());
+        self.wtr.try_set_color(spec)
     }
 
-    /// Get whether this is underline or not.
-    ///
-    /// Note that the underline setting has no effect in a Windows console.
-    pub fn underline(&self) -> bool {
-        self.underline
+    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
+        self.wtr.set_hyperlink(link)
     }
 
-    /// Set whether the text is underlined or not.
-    ///
-    /// Note that the underline setting has no effect in a Windows console.
-    pub fn set_underline(&mut self, yes: bool) -> &mut ColorSpec {
-        self.underline = yes;
-        self
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.wtr.set_hyperlink_with(params, uri)
     }
 
-    /// Get whether this is strikethrough or not.
-    ///
-    /// Note that the strikethrough setting has no effect in a Windows console.
-    pub fn strikethrough(&self) -> bool {
-        self.strikethrough
+    fn reset(&mut self) -> io::Result<()> {
+        self.spec = None;
+        self.wtr.reset()
     }
 
-    /// Set whether the text is strikethrough or not.
-    ///
-    /// Note that the strikethrough setting has no effect in a Windows console.
-    pub fn set_strikethrough(&mut self, yes: bool) -> &mut ColorSpec {
-        self.strikethrough = yes;
-        self
+    fn is_synchronous(&self) -> bool {
+        self.wtr.is_synchronous()
     }
+}
 
-    /// Get whether reset is enabled or not.
-    ///
-    /// reset is enabled by default. When disabled and using ANSI escape
-    /// sequences, a "reset" code will be emitted every time a `ColorSpec`'s
-    /// settings are applied.
-    ///
-    /// Note that the reset setting has no effect in a Windows console.
-    pub fn reset(&self) -> bool {
-        self.reset
+impl WriteColor for io::Sink {
+    fn supports_color(&self) -> bool {
+        false
     }
 
-    /// Set whether to reset the terminal whenever color settings are applied.
-    ///
-    /// reset is enabled by default. When disabled and using ANSI escape
-    /// sequences, a "reset" code will be emitted every time a `ColorSpec`'s
-    /// settings are applied.
-    ///
-    /// Typically this is useful if callers have a requirement to more
-    /// scrupulously manage the exact sequence of escape codes that are emitted
-    /// when using ANSI for colors.
-    ///
-    /// Note that the reset setting has no effect in a Windows console.
-    pub fn set_reset(&mut self, yes: bool) -> &mut ColorSpec {
-        self.reset = yes;
-        self
+    fn supports_hyperlinks(&self) -> bool {
+        false
     }
 
-    /// Get whether this is intense or not.
-    ///
-    /// On Unix-like systems, this will output the ANSI escape sequence
-    /// that will print a high-intensity version of the color
-    /// specified.
-    ///
-    /// On Windows systems, this will output the ANSI escape sequence
-    /// that will print a brighter version of the color specified.
-    pub fn intense(&self) -> bool {
-        self.intense
+    fn set_color(&mut self, _: &ColorSpec) -> io::Result<()> {
+        Ok(())
     }
 
-    /// Set whether the text is intense or not.
-    ///
-    /// On Unix-like systems, this will output the ANSI escape sequence
-    /// that will print a high-intensity version of the color
-    /// specified.
-    ///
-    /// On Windows systems, this will output the ANSI escape sequence
-    /// that will print a brighter version of the color specified.
-    pub fn set_intense(&mut self, yes: bool) -> &mut ColorSpec {
-        self.intense = yes;
-        self
+    fn set_hyperlink(&mut self, _: &HyperlinkSpec) -> io::Result<()> {
+        Ok(())
     }
 
-    /// Returns true if this color specification has no colors or styles.
-    pub fn is_none(&self) -> bool {
-        self.fg_color.is_none()
-            && self.bg_color.is_none()
-            && !self.bold
-            && !self.underline
-            && !self.dimmed
-            && !self.italic
-            && !self.intense
-            && !self.strikethrough
+    fn reset(&mut self) -> io::Result<()> {
+        Ok(())
     }
+}
 
-    /// Clears this color specification so that it has no color/style settings.
-    pub fn clear(&mut self) {
-        self.fg_color = None;
-        self.bg_color = None;
-        self.bold = false;
-        self.underline = false;
-        self.intense = false;
-        self.dimmed = false;
-        self.italic = false;
-        self.strikethrough = false;
+/// A writer that discards all text and color information written to it.
+///
+/// This is like `NoColor<io::Sink>`, and is useful for benchmarking or for
+/// code that needs a `WriteColor` but doesn't care about its output.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sink(());
+
+impl Sink {
+    /// Create a new writer that discards everything written to it.
+    pub fn new() -> Sink {
+        Sink(())
     }
+}
 
-    /// Writes this color spec to the given Windows console.
-    #[cfg(windows)]
-    fn write_console(&self, console: &mut wincon::Console) -> io::Result<()> {
-        let fg_color = self.fg_color.and_then(|c| c.to_windows(self.intense));
-        if let Some((intense, color)) = fg_color {
-            console.fg(intense, color)?;
-        }
-        let bg_color = self.bg_color.and_then(|c| c.to_windows(self.intense));
-        if let Some((intense, color)) = bg_color {
-            console.bg(intense, color)?;
-        }
+impl io::Write for Sink {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
 
-/// The set of available colors for the terminal foreground/background.
-///
-/// The `Ansi256` and `Rgb` colors will only output the correct codes when
-/// paired with the `Ansi` `WriteColor` implementation.
+impl WriteColor for Sink {
+    #[inline]
+    fn supports_color(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn supports_hyperlinks(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn set_color(&mut self, _: &ColorSpec) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn set_hyperlink(&mut self, _: &HyperlinkSpec) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn reset(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer that records the text and color settings written to it.
 ///
-/// The `Ansi256` and `Rgb` color types are not supported when writing colors
-/// on Windows using the console. If they are used on Windows, then they are
-/// silently ignored and no colors will be emitted.
+/// Every operation is forwarded to the inner writer, and those that succeed
+/// are appended to a log of [`Op`]s. This is mostly useful in tests, where
+/// asserting on the log is easier than asserting on escape sequences.
+/// Consecutive writes of text are combined into a single `Op::Text`.
 ///
-/// This set may expand over time.
+/// # Example
 ///
-/// This type has a `FromStr` impl that can parse colors from their human
-/// readable form. The format is as follows:
+/// ```
+/// use std::io::Write;
+/// use termcolor::{Color, ColorSpec, NoColor, Op, Record, WriteColor};
 ///
-/// 1. Any of the explicitly listed colors in English. They are matched
-///    case insensitively.
-/// 2. A single 8-bit integer, in either decimal or hexadecimal format.
-/// 3. A triple of 8-bit integers separated by a comma, where each integer is
-///    in decimal or hexadecimal format.
+/// let mut spec = ColorSpec::new();
+/// spec.set_fg(Some(Color::Red));
 ///
-/// Hexadecimal numbers are written with a `0x` prefix.
-#[allow(missing_docs)]
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum Color {
-    Black,
-    Blue,
-    Green,
-    Red,
-    Cyan,
-    Magenta,
-    Yellow,
-    White,
-    Ansi256(u8),
-    Rgb(u8, u8, u8),
-    #[doc(hidden)]
-    __Nonexhaustive,
+/// let mut wtr = Record::new(NoColor::new(vec![]));
+/// wtr.set_color(&spec)?;
+/// write!(wtr, "error: {}", 42)?;
+/// wtr.reset()?;
+/// assert_eq!(
+///     wtr.ops(),
+///     &[Op::SetColor(spec), Op::Text(b"error: 42".to_vec()), Op::Reset],
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Record<W> {
+    wtr: W,
+    ops: Vec<Op>,
 }
 
-impl Color {
-    /// Translate this color to a wincon::Color.
-    #[cfg(windows)]
-    fn to_windows(
-        self,
-        intense: bool,
-    ) -> Option<(wincon::Intense, wincon::Color)> {
-        use wincon::Intense::{No, Yes};
+/// An operation recorded by [`Record`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Op {
+    /// Text that was written.
+    Text(Vec<u8>),
+    /// A call to `set_color` with the given specification.
+    SetColor(ColorSpec),
+    /// A call to `set_hyperlink`, where `None` closes the hyperlink.
+    SetHyperlink(Option<Vec<u8>>),
+    /// A call to `reset`.
+    Reset,
+}
 
-        let color = match self {
-            Color::Black => wincon::Color::Black,
-            Color::Blue => wincon::Color::Blue,
-            Color::Green => wincon::Color::Green,
-            Color::Red => wincon::Color::Red,
-            Color::Cyan => wincon::Color::Cyan,
-            Color::Magenta => wincon::Color::Magenta,
-            Color::Yellow => wincon::Color::Yellow,
-            Color::White => wincon::Color::White,
-            Color::Ansi256(0) => return Some((No, wincon::Color::Black)),
-            Color::Ansi256(1) => return Some((No, wincon::Color::Red)),
-            Color::Ansi256(2) => return Some((No, wincon::Color::Green)),
-            Color::Ansi256(3) => return Some((No, wincon::Color::Yellow)),
-            Color::Ansi256(4) => return Some((No, wincon::Color::Blue)),
-            Color::Ansi256(5) => return Some((No, wincon::Color::Magenta)),
-            Color::Ansi256(6) => return Some((No, wincon::Color::Cyan)),
-            Color::Ansi256(7) => return Some((No, wincon::Color::White)),
-            Color::Ansi256(8) => return Some((Yes, wincon::Color::Black)),
-            Color::Ansi256(9) => return Some((Yes, wincon::Color::Red)),
-            Color::Ansi256(10) => return Some((Yes, wincon::Color::Green)),
-            Color::Ansi256(11) => return Some((Yes, wincon::Color::Yellow)),
-            Color::Ansi256(12) => return Some((Yes, wincon::Color::Blue)),
-            Color::Ansi256(13) => return Some((Yes, wincon::Color::Magenta)),
-            Color::Ansi256(14) => return Some((Yes, wincon::Color::Cyan)),
-            Color::Ansi256(15) => return Some((Yes, wincon::Color::White)),
-            Color::Ansi256(_) => return None,
-            Color::Rgb(_, _, _) => return None,
-            Color::__Nonexhaustive => unreachable!(),
-        };
-        let intense = if intense { Yes } else { No };
-        Some((intense, color))
+impl<W: WriteColor> Record<W> {
+    /// Create a new writer that records everything written to `wtr`.
+    pub fn new(wtr: W) -> Record<W> {
+        Record { wtr, ops: vec![] }
     }
 
-    /// Parses a numeric color string, either ANSI or RGB.
-    fn from_str_numeric(s: &str) -> Result<Color, ParseColorError> {
-        // The "ansi256" format is a single number (decimal or hex)
-        // corresponding to one of 256 colors.
-        //
-        // The "rgb" format is a triple of numbers (decimal or hex) delimited
-        // by a comma corresponding to one of 256^3 colors.
+    /// Return the operations recorded so far.
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
 
-        fn parse_number(s: &str) -> Option<u8> {
-            use std::u8;
+    /// Remove and return the operations recorded so far.
+    pub fn take_ops(&mut self) -> Vec<Op> {
+        mem::take(&mut self.ops)
+    }
 
-            if s.starts_with("0x") {
-                u8::from_str_radix(&s[2..], 16).ok()
-            } else {
-                u8::from_str_radix(s, 10).ok()
-            }
-        }
+    /// Consume this `Record` value and return the inner writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
 
-        let codes: Vec<&str> = s.split(',').collect();
-        if codes.len() == 1 {
-            if let Some(n) = parse_number(&codes[0]) {
-                Ok(Color::Ansi256(n))
-            } else {
-                if s.chars().all(|c| c.is_digit(16)) {
-                    Err(ParseColorError {
-                        kind: ParseColorErrorKind::InvalidAnsi256,
-                        given: s.to_string(),
-                    })
-                } else {
-                    Err(ParseColorError {
-                        kind: ParseColorErrorKind::InvalidName,
-                        given: s.to_string(),
-                    })
+    /// Return a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.wtr
+    }
+
+    /// Return a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.wtr
+    }
+}
+
+impl<W: WriteColor> io::Write for Record<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.wtr.write(buf)?;
+        if n > 0 {
+            match self.ops.last_mut() {
+                Some(Op::Text(ref mut text)) => {
+                    text.extend_from_slice(&buf[..n])
                 }
+                _ => self.ops.push(Op::Text(buf[..n].to_vec())),
             }
-        } else if codes.len() == 3 {
-            let mut v = vec![];
-            for code in codes {
-                let n = parse_number(code).ok_or_else(|| ParseColorError {
-                    kind: ParseColorErrorKind::InvalidRgb,
-                    given: s.to_string(),
-                })?;
-                v.push(n);
-            }
-            Ok(Color::Rgb(v[0], v[1], v[2]))
-        } else {
-            Err(if s.contains(",") {
-                ParseColorError {
-                    kind: ParseColorErrorKind::InvalidRgb,
-                    given: s.to_string(),
-                }
-            } else {
-                ParseColorError {
-                    kind: ParseColorErrorKind::InvalidName,
-                    given: s.to_string(),
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+impl<W: WriteColor> WriteColor for Record<W> {
+    fn supports_color(&self) -> bool {
+        self.wtr.supports_color()
+    }
+
+    fn supports_hyperlinks(&self) -> bool {
+        self.wtr.supports_hyperlinks()
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.wtr.set_color(spec)?;
+        self.ops.push(Op::SetColor(spec.clone()));
+        Ok(())
+    }
+
+    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
+        self.wtr.set_hyperlink(link)?;
+        self.ops.push(Op::SetHyperlink(link.uri().map(|uri| uri.to_vec())));
+        Ok(())
+    }
+
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.wtr.set_hyperlink_with(params, uri)?;
+        self.ops.push(Op::SetHyperlink(Some(uri.to_vec())));
+        Ok(())
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.wtr.reset()?;
+        self.ops.push(Op::Reset);
+        Ok(())
+    }
+
+    fn is_synchronous(&self) -> bool {
+        self.wtr.is_synchronous()
+    }
+}
+
+/// The operations used to set colors on a Windows console.
+///
+/// This is implemented by `wincon::Console`, and exists so that the logic
+/// for applying colors can be tested without a real console.
+#[cfg(windows)]
+trait ConsoleLike {
+    /// Set the foreground color.
+    fn fg(
+        &mut self,
+        intense: wincon::Intense,
+        color: wincon::Color,
+    ) -> io::Result<()>;
+
+    /// Set the background color.
+    fn bg(
+        &mut self,
+        intense: wincon::Intense,
+        color: wincon::Color,
+    ) -> io::Result<()>;
+
+    /// Restore the colors the console had when it was opened.
+    fn reset(&mut self) -> io::Result<()>;
+}
+
+#[cfg(windows)]
+impl ConsoleLike for wincon::Console {
+    fn fg(
+        &mut self,
+        intense: wincon::Intense,
+        color: wincon::Color,
+    ) -> io::Result<()> {
+        wincon::Console::fg(self, intense, color)
+    }
+
+    fn bg(
+        &mut self,
+        intense: wincon::Intense,
+        color: wincon::Color,
+    ) -> io::Result<()> {
+        wincon::Console::bg(self, intense, color)
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        wincon::Console::reset(self)
+    }
+}
+
+/// An in-memory buffer that provides Windows console coloring.
+///
+/// This doesn't actually communicate with the Windows console. Instead, it
+/// acts like a normal buffer but also saves the color information associated
+/// with positions in the buffer. It is only when the buffer is written to the
+/// console that coloring is actually applied.
+///
+/// This is roughly isomorphic to the ANSI based approach (i.e.,
+/// `Ansi<Vec<u8>>`), except with ANSI, the color information is embedded
+/// directly into the buffer.
+///
+/// Note that there is no way to write something generic like
+/// `WindowsConsole<W: io::Write>` since coloring on Windows is tied
+/// specifically to the console APIs, and therefore can't work on arbitrary
+/// writers.
+#[cfg(windows)]
+#[derive(Clone, Debug)]
+struct WindowsBuffer {
+    /// The actual content that should be printed.
+    buf: Vec<u8>,
+    /// A sequence of position oriented color specifications. Namely, each
+    /// element is a position and a color spec, where the color spec should
+    /// be applied at the position inside of `buf`.
+    ///
+    /// A missing color spec implies the underlying console should be reset.
+    colors: Vec<(usize, Option<ColorSpec>)>,
+}
+
+#[cfg(windows)]
+impl WindowsBuffer {
+    /// Create a new empty buffer for Windows console coloring.
+    fn new() -> WindowsBuffer {
+        WindowsBuffer { buf: vec![], colors: vec![] }
+    }
+
+    /// Push the given color specification into this buffer.
+    ///
+    /// This has the effect of setting the given color information at the
+    /// current position in the buffer.
+    fn push(&mut self, spec: Option<ColorSpec>) {
+        let pos = self.buf.len();
+        self.colors.push((pos, spec));
+    }
+
+    /// Print the contents to the given stream handle, and use the console
+    /// for coloring.
+    fn print<C: ConsoleLike, W: io::Write>(
+        &self,
+        console: &mut C,
+        stream: &mut W,
+        bold_as_intense: bool,
+    ) -> io::Result<()> {
+        let mut last = 0;
+        for &(pos, ref spec) in &self.colors {
+            stream.write_all(&self.buf[last..pos])?;
+            stream.flush()?;
+            last = pos;
+            match *spec {
+                None => console.reset()?,
+                Some(ref spec) => {
+                    spec.write_console(console, bold_as_intense)?
                 }
-            })
+            }
+        }
+        stream.write_all(&self.buf[last..])?;
+        stream.flush()
+    }
+
+    /// Clear the buffer.
+    fn clear(&mut self) {
+        self.buf.clear();
+        self.colors.clear();
+    }
+
+    /// Append the contents and color information of the given buffer.
+    fn append(&mut self, other: &WindowsBuffer) {
+        let offset = self.buf.len();
+        self.buf.extend_from_slice(&other.buf);
+        self.colors.extend(
+            other
+                .colors
+                .iter()
+                .map(|(pos, spec)| (offset + pos, spec.clone())),
+        );
+    }
+}
+
+#[cfg(windows)]
+impl io::Write for WindowsBuffer {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl WriteColor for WindowsBuffer {
+    #[inline]
+    fn supports_color(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn supports_hyperlinks(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.push(Some(spec.clone()));
+        Ok(())
+    }
+
+    #[inline]
+    fn set_hyperlink(&mut self, _: &HyperlinkSpec) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn reset(&mut self) -> io::Result<()> {
+        self.push(None);
+        Ok(())
+    }
+
+    #[inline]
+    fn is_synchronous(&self) -> bool {
+        false
+    }
+}
+
+/// A color specification.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ColorSpec {
+    fg_color: Option<Color>,
+    bg_color: Option<Color>,
+    bold: bool,
+    intense: bool,
+    underline: bool,
+    underline_style: UnderlineStyle,
+    dimmed: bool,
+    italic: bool,
+    reset: bool,
+    strikethrough: bool,
+}
+
+impl Default for ColorSpec {
+    fn default() -> ColorSpec {
+        ColorSpec {
+            fg_color: None,
+            bg_color: None,
+            bold: false,
+            intense: false,
+            underline: false,
+            underline_style: UnderlineStyle::Straight,
+            dimmed: false,
+            italic: false,
+            reset: true,
+            strikethrough: false,
+        }
+    }
+}
+
+impl ColorSpec {
+    /// Create a new color specification that has no colors or styles.
+    pub fn new() -> ColorSpec {
+        ColorSpec::default()
+    }
+
+    /// Read a color specification from the given environment variable.
+    ///
+    /// The value of the variable is parsed using the `FromStr` impl for
+    /// `ColorSpec`. If the variable isn't set, then `None` is returned. If it
+    /// is set but can't be parsed (or isn't valid UTF-8), then an error is
+    /// returned.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use termcolor::ColorSpec;
+    ///
+    /// let spec = match ColorSpec::from_env("MYTOOL_COLORS") {
+    ///     None => ColorSpec::new(),
+    ///     Some(Ok(spec)) => spec,
+    ///     Some(Err(err)) => {
+    ///         eprintln!("invalid MYTOOL_COLORS: {}", err);
+    ///         ColorSpec::new()
+    ///     }
+    /// };
+    /// ```
+    pub fn from_env(var: &str) -> Option<Result<ColorSpec, ParseColorError>> {
+        match env::var(var) {
+            Ok(value) => Some(value.parse()),
+            Err(env::VarError::NotPresent) => None,
+            Err(env::VarError::NotUnicode(value)) => {
+                Some(Err(ParseColorError {
+                    kind: ParseColorErrorKind::InvalidSpec,
+                    given: value.to_string_lossy().into_owned(),
+                }))
+            }
+        }
+    }
+
+    /// Get the foreground color.
+    pub fn fg(&self) -> Option<&Color> {
+        self.fg_color.as_ref()
+    }
+
+    /// Set the foreground color.
+    pub fn set_fg(&mut self, color: Option<Color>) -> &mut ColorSpec {
+        self.fg_color = color;
+        self
+    }
+
+    /// Clear the foreground color.
+    ///
+    /// This is equivalent to `set_fg(None)`.
+    pub fn clear_fg(&mut self) -> &mut ColorSpec {
+        self.set_fg(None)
+    }
+
+    /// Get the background color.
+    pub fn bg(&self) -> Option<&Color> {
+        self.bg_color.as_ref()
+    }
+
+    /// Set the background color.
+    pub fn set_bg(&mut self, color: Option<Color>) -> &mut ColorSpec {
+        self.bg_color = color;
+        self
+    }
+
+    /// Clear the background color.
+    ///
+    /// This is equivalent to `set_bg(None)`.
+    pub fn clear_bg(&mut self) -> &mut ColorSpec {
+        self.set_bg(None)
+    }
+
+    /// Get whether this is bold or not.
+    ///
+    /// A Windows console cannot render bold text, so bold has no effect when
+    /// colors are set via the console APIs. Writers can opt into
+    /// approximating bold by using the high intensity variant of the
+    /// foreground color instead, via [`StandardStream::set_bold_as_intense`]
+    /// or [`BufferWriter::set_bold_as_intense`].
+    pub fn bold(&self) -> bool {
+        self.bold
+    }
+
+    /// Set whether the text is bolded or not.
+    ///
+    /// A Windows console cannot render bold text, so bold has no effect when
+    /// colors are set via the console APIs. Writers can opt into
+    /// approximating bold by using the high intensity variant of the
+    /// foreground color instead, via [`StandardStream::set_bold_as_intense`]
+    /// or [`BufferWriter::set_bold_as_intense`].
+    pub fn set_bold(&mut self, yes: bool) -> &mut ColorSpec {
+        self.bold = yes;
+        self
+    }
+
+    /// Get whether this is dimmed or not.
+    ///
+    /// Note that the dimmed setting has no effect in a Windows console.
+    pub fn dimmed(&self) -> bool {
+        self.dimmed
+    }
+
+    /// Set whether the text is dimmed or not.
+    ///
+    /// Note that the dimmed setting has no effect in a Windows console.
+    pub fn set_dimmed(&mut self, yes: bool) -> &mut ColorSpec {
+        self.dimmed = yes;
+        self
+    }
+
+    /// Get whether this is italic or not.
+    ///
+    /// Note that the italic setting has no effect in a Windows console.
+    pub fn italic(&self) -> bool {
+        self.italic
+    }
+
+    /// Set whether the text is italicized or not.
+    ///
+    /// Note that the italic setting has no effect in a Windows console.
+    pub fn set_italic(&mut self, yes: bool) -> &mut ColorSpec {
+        self.italic = yes;
+        self
+    }
+
+    /// Get whether this is underline or not.
+    ///
+    /// Note that the underline setting has no effect in a Windows console.
+    pub fn underline(&self) -> bool {
+        self.underline
+    }
+
+    /// Set whether the text is underlined or not.
+    ///
+    /// Note that the underline setting has no effect in a Windows console.
+    pub fn set_underline(&mut self, yes: bool) -> &mut ColorSpec {
+        self.underline = yes;
+        self
+    }
+
+    /// Get the style of underline used when the text is underlined.
+    pub fn underline_style(&self) -> UnderlineStyle {
+        self.underline_style
+    }
+
+    /// Set the style of underline used when the text is underlined.
+    ///
+    /// The style only has an effect when underlining is enabled via
+    /// `set_underline`. Styles other than `UnderlineStyle::Straight` use an
+    /// extension to the ANSI underline sequence that is supported by
+    /// terminals such as Kitty and those based on VTE. Other terminals
+    /// typically either ignore it or render a straight underline.
+    ///
+    /// Note that the underline setting has no effect in a Windows console.
+    ///
+    /// The default style is `UnderlineStyle::Straight`.
+    pub fn set_underline_style(
+        &mut self,
+        style: UnderlineStyle,
+    ) -> &mut ColorSpec {
+        self.underline_style = style;
+        self
+    }
+
+    /// Get whether this is strikethrough or not.
+    ///
+    /// Note that the strikethrough setting has no effect in a Windows console.
+    pub fn strikethrough(&self) -> bool {
+        self.strikethrough
+    }
+
+    /// Set whether the text is strikethrough or not.
+    ///
+    /// Note that the strikethrough setting has no effect in a Windows console.
+    pub fn set_strikethrough(&mut self, yes: bool) -> &mut ColorSpec {
+        self.strikethrough = yes;
+        self
+    }
+
+    /// Get whether reset is enabled or not.
+    ///
+    /// reset is enabled by default. When disabled and using ANSI escape
+    /// sequences, a "reset" code will be emitted every time a `ColorSpec`'s
+    /// settings are applied.
+    ///
+    /// Note that the reset setting has no effect in a Windows console.
+    pub fn reset(&self) -> bool {
+        self.reset
+    }
+
+    /// Set whether to reset the terminal whenever color settings are applied.
+    ///
+    /// reset is enabled by default. When disabled and using ANSI escape
+    /// sequences, a "reset" code will be emitted every time a `ColorSpec`'s
+    /// settings are applied.
+    ///
+    /// Typically this is useful if callers have a requirement to more
+    /// scrupulously manage the exact sequence of escape codes that are emitted
+    /// when using ANSI for colors.
+    ///
+    /// Note that the reset setting has no effect in a Windows console.
+    pub fn set_reset(&mut self, yes: bool) -> &mut ColorSpec {
+        self.reset = yes;
+        self
+    }
+
+    /// Get whether this is intense or not.
+    ///
+    /// On Unix-like systems, this will output the ANSI escape sequence
+    /// that will print a high-intensity version of the color
+    /// specified.
+    ///
+    /// On Windows systems, this will output the ANSI escape sequence
+    /// that will print a brighter version of the color specified.
+    pub fn intense(&self) -> bool {
+        self.intense
+    }
+
+    /// Set whether the text is intense or not.
+    ///
+    /// On Unix-like systems, this will output the ANSI escape sequence
+    /// that will print a high-intensity version of the color
+    /// specified.
+    ///
+    /// On Windows systems, this will output the ANSI escape sequence
+    /// that will print a brighter version of the color specified.
+    pub fn set_intense(&mut self, yes: bool) -> &mut ColorSpec {
+        self.intense = yes;
+        self
+    }
+
+    /// Returns true if this color specification has no colors or styles.
+    pub fn is_none(&self) -> bool {
+        self.fg_color.is_none()
+            && self.bg_color.is_none()
+            && !self.bold
+            && !self.underline
+            && !self.dimmed
+            && !self.italic
+            && !self.intense
+            && !self.strikethrough
+    }
+
+    /// Returns true if this color specification has styles but no colors.
+    ///
+    /// Writing such a specification never requires a 256 color or RGB
+    /// sequence. Every specification is exactly one of empty (as reported by
+    /// [`ColorSpec::is_none`]), attribute only, or one with colors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::{Color, ColorSpec};
+    ///
+    /// let mut spec = ColorSpec::new();
+    /// assert!(!spec.is_attr_only());
+    /// spec.set_bold(true);
+    /// assert!(spec.is_attr_only());
+    /// spec.set_fg(Some(Color::Red));
+    /// assert!(!spec.is_attr_only());
+    /// ```
+    pub fn is_attr_only(&self) -> bool {
+        self.fg_color.is_none()
+            && self.bg_color.is_none()
+            && !self.attrs().is_empty()
+    }
+
+    /// Returns the styles that are set on this specification.
+    ///
+    /// This includes every boolean style, but not the colors, the underline
+    /// style or the reset setting.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::{Attributes, ColorSpec};
+    ///
+    /// let mut spec = ColorSpec::new();
+    /// spec.set_bold(true).set_italic(true);
+    /// assert_eq!(spec.attrs(), Attributes::BOLD | Attributes::ITALIC);
+    /// ```
+    pub fn attrs(&self) -> Attributes {
+        let mut attrs = Attributes::NONE;
+        let flags = [
+            (self.bold, Attributes::BOLD),
+            (self.dimmed, Attributes::DIMMED),
+            (self.italic, Attributes::ITALIC),
+            (self.underline, Attributes::UNDERLINE),
+            (self.strikethrough, Attributes::STRIKETHROUGH),
+            (self.intense, Attributes::INTENSE),
+        ];
+        for &(yes, attr) in &flags {
+            if yes {
+                attrs |= attr;
+            }
+        }
+        attrs
+    }
+
+    /// Set all of the styles on this specification at once.
+    ///
+    /// Each style is enabled if it is in `attrs` and disabled otherwise. The
+    /// colors, the underline style and the reset setting are unchanged.
+    pub fn set_attrs(&mut self, attrs: Attributes) -> &mut ColorSpec {
+        self.bold = attrs.contains(Attributes::BOLD);
+        self.dimmed = attrs.contains(Attributes::DIMMED);
+        self.italic = attrs.contains(Attributes::ITALIC);
+        self.underline = attrs.contains(Attributes::UNDERLINE);
+        self.strikethrough = attrs.contains(Attributes::STRIKETHROUGH);
+        self.intense = attrs.contains(Attributes::INTENSE);
+        self
+    }
+
+    /// Returns the colors and styles that are set on this specification.
+    ///
+    /// Colors come first, followed by styles. A specification for which
+    /// [`ColorSpec::is_none`] returns true has no active attributes.
+    ///
+    /// The `reset` setting is not considered an attribute.
+    pub fn active_attributes(&self) -> Vec<AttributeKind> {
+        let mut attrs = vec![];
+        if let Some(color) = self.fg_color {
+            attrs.push(AttributeKind::Fg(color));
+        }
+        if let Some(color) = self.bg_color {
+            attrs.push(AttributeKind::Bg(color));
+        }
+        if self.bold {
+            attrs.push(AttributeKind::Bold);
+        }
+        if self.dimmed {
+            attrs.push(AttributeKind::Dimmed);
+        }
+        if self.italic {
+            attrs.push(AttributeKind::Italic);
+        }
+        if self.underline {
+            attrs.push(AttributeKind::Underline);
+        }
+        if self.strikethrough {
+            attrs.push(AttributeKind::Strikethrough);
+        }
+        if self.intense {
+            attrs.push(AttributeKind::Intense);
+        }
+        attrs
+    }
+
+    /// Returns a copy of this specification whose colors have been mapped to
+    /// the closest colors supported at the given level.
+    ///
+    /// For `ColorLevel::None`, all colors are removed. For
+    /// `ColorLevel::Basic`, `Rgb` colors and `Ansi256` colors outside of
+    /// the first 16 are mapped to the nearest of the 16 basic colors. The
+    /// eight intense basic colors are represented as `Ansi256(8)` through
+    /// `Ansi256(15)`. For `ColorLevel::Ansi256`, `Rgb` colors are mapped to
+    /// the nearest color in the xterm 256 color palette. For
+    /// `ColorLevel::TrueColor`, the specification is returned unchanged.
+    ///
+    /// Distances are computed using the RGB values of the default xterm
+    /// palette. All styles (such as bold) are preserved at every level.
+    pub fn to_lossy(&self, level: ColorLevel) -> ColorSpec {
+        let mut spec = self.clone();
+        spec.fg_color = self.fg_color.and_then(|c| c.to_level(level));
+        spec.bg_color = self.bg_color.and_then(|c| c.to_level(level));
+        spec
+    }
+
+    /// Returns true if this specification is equal to `other` when the fields
+    /// selected by `mask` are ignored.
+    ///
+    /// This is useful for comparing specifications without cloning and
+    /// mutating them first. For example, `ColorSpecMask::BOLD` compares two
+    /// specifications while ignoring whether either one is bold.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::{Color, ColorSpec, ColorSpecMask};
+    ///
+    /// let mut spec1 = ColorSpec::new();
+    /// spec1.set_fg(Some(Color::Red)).set_bold(true);
+    /// let mut spec2 = ColorSpec::new();
+    /// spec2.set_fg(Some(Color::Red));
+    ///
+    /// assert!(!spec1.eq_ignoring(&spec2, ColorSpecMask::NONE));
+    /// assert!(spec1.eq_ignoring(&spec2, ColorSpecMask::BOLD));
+    /// ```
+    pub fn eq_ignoring(&self, other: &ColorSpec, mask: ColorSpecMask) -> bool {
+        let field = |m: ColorSpecMask, eq: bool| mask.contains(m) || eq;
+        field(ColorSpecMask::FG, self.fg_color == other.fg_color)
+            && field(ColorSpecMask::BG, self.bg_color == other.bg_color)
+            && field(ColorSpecMask::BOLD, self.bold == other.bold)
+            && field(ColorSpecMask::INTENSE, self.intense == other.intense)
+            && field(
+                ColorSpecMask::UNDERLINE,
+                self.underline == other.underline
+                    && self.underline_style == other.underline_style,
+            )
+            && field(ColorSpecMask::DIMMED, self.dimmed == other.dimmed)
+            && field(ColorSpecMask::ITALIC, self.italic == other.italic)
+            && field(ColorSpecMask::RESET, self.reset == other.reset)
+            && field(
+                ColorSpecMask::STRIKETHROUGH,
+                self.strikethrough == other.strikethrough,
+            )
+    }
+
+    /// Merges the settings of `other` into this specification.
+    ///
+    /// Every setting in `other` that differs from its default value takes
+    /// precedence, while the remaining settings are left as is. That is,
+    /// colors are only replaced when `other` sets them, styles are only
+    /// enabled (never disabled), and the reset setting is only disabled. The
+    /// underline style is replaced when `other` is underlined.
+    ///
+    /// This is also available as the `|` and `|=` operators.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::{Color, ColorSpec};
+    ///
+    /// let mut base = ColorSpec::new();
+    /// base.set_fg(Some(Color::Red)).set_bg(Some(Color::Black));
+    /// let mut emphasis = ColorSpec::new();
+    /// emphasis.set_fg(Some(Color::Yellow)).set_bold(true);
+    ///
+    /// base.merge(&emphasis);
+    /// assert_eq!(base.fg(), Some(&Color::Yellow));
+    /// assert_eq!(base.bg(), Some(&Color::Black));
+    /// assert!(base.bold());
+    /// ```
+    pub fn merge(&mut self, other: &ColorSpec) -> &mut ColorSpec {
+        if other.fg_color.is_some() {
+            self.fg_color = other.fg_color;
+        }
+        if other.bg_color.is_some() {
+            self.bg_color = other.bg_color;
+        }
+        if other.underline {
+            self.underline = true;
+            self.underline_style = other.underline_style;
+        }
+        self.bold |= other.bold;
+        self.intense |= other.intense;
+        self.dimmed |= other.dimmed;
+        self.italic |= other.italic;
+        self.strikethrough |= other.strikethrough;
+        self.reset &= other.reset;
+        self
+    }
+
+    /// Returns the ANSI escape sequences that change the terminal from the
+    /// `prev` color settings to the settings in this spec.
+    ///
+    /// Unlike writing this spec with [`Ansi`], which resets all settings and
+    /// then applies the spec from scratch, this only emits the codes for the
+    /// settings that differ. The transition rules are:
+    ///
+    /// * An attribute that is enabled in this spec but not in `prev` is
+    ///   turned on with the same code that `Ansi` uses.
+    /// * An attribute that is enabled in `prev` but not in this spec is
+    ///   turned off: `22` for bold and dimmed, `23` for italic, `24` for
+    ///   underline and `29` for strikethrough. Since `22` turns off both
+    ///   bold and dimmed, whichever of the two remains enabled is turned
+    ///   back on afterwards.
+    /// * If both specs are underlined but with different styles, then the
+    ///   new underline style is written.
+    /// * A foreground or background color that differs is written, or is
+    ///   turned off with `39` or `49` respectively when this spec has no
+    ///   color. Colors are also rewritten when the intense setting differs,
+    ///   since it changes how colors are written.
+    ///
+    /// The reset setting of both specs is ignored, since it doesn't describe
+    /// the state of the terminal. Colors are written as they would be by an
+    /// [`Ansi`] writer with the default settings, i.e., without a palette
+    /// and at `ColorLevel::TrueColor`. If both specs are equal, then the
+    /// string returned is empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::{Color, ColorSpec};
+    ///
+    /// let mut prev = ColorSpec::new();
+    /// prev.set_fg(Some(Color::Red)).set_bold(true);
+    /// let mut next = ColorSpec::new();
+    /// next.set_fg(Some(Color::Blue)).set_bold(true);
+    /// assert_eq!(next.diff_ansi(&prev), "\x1B[34m");
+    /// ```
+    pub fn diff_ansi(&self, prev: &ColorSpec) -> String {
+        let mut wtr = Ansi::new(vec![]);
+        // Writing to a `Vec<u8>` can't fail.
+        wtr.write_spec_diff(prev, self).unwrap();
+        // Escape sequences are always ASCII.
+        String::from_utf8(wtr.into_inner()).unwrap()
+    }
+
+    /// Clears this color specification so that it has no color/style settings.
+    pub fn clear(&mut self) {
+        self.fg_color = None;
+        self.bg_color = None;
+        self.bold = false;
+        self.underline = false;
+        self.underline_style = UnderlineStyle::Straight;
+        self.intense = false;
+        self.dimmed = false;
+        self.italic = false;
+        self.strikethrough = false;
+    }
+
+    /// Writes this color spec to the given Windows console.
+    ///
+    /// When `bold_as_intense` is true, bold text is approximated by using
+    /// the high intensity variant of the foreground color.
+    #[cfg(windows)]
+    fn write_console<C: ConsoleLike>(
+        &self,
+        console: &mut C,
+        bold_as_intense: bool,
+    ) -> io::Result<()> {
+        let fg_color = self.fg_color.and_then(|c| {
+            c.to_windows(self.console_fg_intense(bold_as_intense))
+        });
+        if let Some((intense, color)) = fg_color {
+            console.fg(intense, color)?;
+        }
+        let bg_color = self.bg_color.and_then(|c| c.to_windows(self.intense));
+        if let Some((intense, color)) = bg_color {
+            console.bg(intense, color)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the foreground color should use its high intensity
+    /// variant when written to a Windows console.
+    ///
+    /// The console has no notion of bold text, so when `bold_as_intense` is
+    /// true, bold is approximated by making the foreground intense. The
+    /// background is only made intense when `intense` is set, since bold text
+    /// shouldn't change the color behind it.
+    #[cfg(windows)]
+    fn console_fg_intense(&self, bold_as_intense: bool) -> bool {
+        self.intense || (bold_as_intense && self.bold)
+    }
+}
+
+impl std::ops::BitOr for ColorSpec {
+    type Output = ColorSpec;
+
+    fn bitor(self, rhs: ColorSpec) -> ColorSpec {
+        self | &rhs
+    }
+}
+
+impl std::ops::BitOr<&ColorSpec> for ColorSpec {
+    type Output = ColorSpec;
+
+    fn bitor(mut self, rhs: &ColorSpec) -> ColorSpec {
+        self.merge(rhs);
+        self
+    }
+}
+
+impl std::ops::BitOrAssign<&ColorSpec> for ColorSpec {
+    fn bitor_assign(&mut self, rhs: &ColorSpec) {
+        self.merge(rhs);
+    }
+}
+
+/// A single color or style that may be set on a [`ColorSpec`].
+///
+/// This is returned by [`ColorSpec::active_attributes`].
+///
+/// This set may expand over time, so matching on an `AttributeKind` requires
+/// a wildcard arm.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum AttributeKind {
+    /// A foreground color.
+    Fg(Color),
+    /// A background color.
+    Bg(Color),
+    /// Bold text.
+    Bold,
+    /// Dimmed text.
+    Dimmed,
+    /// Italic text.
+    Italic,
+    /// Underlined text.
+    Underline,
+    /// Strikethrough text.
+    Strikethrough,
+    /// High intensity colors.
+    Intense,
+}
+
+/// The style of an underline.
+///
+/// This is set on a [`ColorSpec`] via [`ColorSpec::set_underline_style`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum UnderlineStyle {
+    /// A single straight line.
+    #[default]
+    Straight,
+    /// Two straight lines.
+    Double,
+    /// A single wavy line.
+    Curly,
+    /// A single dotted line.
+    Dotted,
+    /// A single dashed line.
+    Dashed,
+}
+
+/// A set of [`ColorSpec`] fields.
+///
+/// This is used by [`ColorSpec::eq_ignoring`] to select the fields that
+/// should be ignored when comparing two specifications. Masks can be
+/// combined with the `|` operator.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ColorSpecMask(u16);
+
+impl ColorSpecMask {
+    /// The empty set of fields.
+    pub const NONE: ColorSpecMask = ColorSpecMask(0);
+    /// The foreground color.
+    pub const FG: ColorSpecMask = ColorSpecMask(1 << 0);
+    /// The background color.
+    pub const BG: ColorSpecMask = ColorSpecMask(1 << 1);
+    /// The bold attribute.
+    pub const BOLD: ColorSpecMask = ColorSpecMask(1 << 2);
+    /// The intense attribute.
+    pub const INTENSE: ColorSpecMask = ColorSpecMask(1 << 3);
+    /// The underline attribute, including its style.
+    pub const UNDERLINE: ColorSpecMask = ColorSpecMask(1 << 4);
+    /// The dimmed attribute.
+    pub const DIMMED: ColorSpecMask = ColorSpecMask(1 << 5);
+    /// The italic attribute.
+    pub const ITALIC: ColorSpecMask = ColorSpecMask(1 << 6);
+    /// The reset setting.
+    pub const RESET: ColorSpecMask = ColorSpecMask(1 << 7);
+    /// The strikethrough attribute.
+    pub const STRIKETHROUGH: ColorSpecMask = ColorSpecMask(1 << 8);
+    /// Both colors.
+    pub const COLORS: ColorSpecMask = ColorSpecMask(0b11);
+    /// Every field other than the colors.
+    pub const ATTRIBUTES: ColorSpecMask = ColorSpecMask(0b1_1111_1100);
+    /// Every field.
+    pub const ALL: ColorSpecMask = ColorSpecMask(0b1_1111_1111);
+
+    /// Returns true if every field in `other` is also in this mask.
+    pub fn contains(self, other: ColorSpecMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for ColorSpecMask {
+    type Output = ColorSpecMask;
+
+    fn bitor(self, rhs: ColorSpecMask) -> ColorSpecMask {
+        ColorSpecMask(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for ColorSpecMask {
+    fn bitor_assign(&mut self, rhs: ColorSpecMask) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// A set of the styles that may be enabled on a [`ColorSpec`].
+///
+/// This is returned by [`ColorSpec::attrs`] and set with
+/// [`ColorSpec::set_attrs`]. Styles can be combined with the `|` operator.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Attributes(u8);
+
+impl Attributes {
+    /// The empty set of styles.
+    pub const NONE: Attributes = Attributes(0);
+    /// Bold text.
+    pub const BOLD: Attributes = Attributes(1 << 0);
+    /// Dimmed text.
+    pub const DIMMED: Attributes = Attributes(1 << 1);
+    /// Italic text.
+    pub const ITALIC: Attributes = Attributes(1 << 2);
+    /// Underlined text.
+    pub const UNDERLINE: Attributes = Attributes(1 << 3);
+    /// Strikethrough text.
+    pub const STRIKETHROUGH: Attributes = Attributes(1 << 4);
+    /// High intensity colors.
+    pub const INTENSE: Attributes = Attributes(1 << 5);
+
+    /// Returns true if every style in `other` is also in this set.
+    pub fn contains(self, other: Attributes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if this set has no styles.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Attributes {
+    type Output = Attributes;
+
+    fn bitor(self, rhs: Attributes) -> Attributes {
+        Attributes(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Attributes {
+    fn bitor_assign(&mut self, rhs: Attributes) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// The set of available colors for the terminal foreground/background.
+///
+/// The `Ansi256` and `Rgb` colors will only output the correct codes when
+/// paired with the `Ansi` `WriteColor` implementation.
+///
+/// The `Ansi256` and `Rgb` color types are not supported when writing colors
+/// on Windows using the console. If they are used on Windows, then they are
+/// silently ignored and no colors will be emitted.
+///
+/// This set may expand over time, so matching on a `Color` requires a
+/// wildcard arm. For example, this doesn't compile:
+///
+/// ```compile_fail,E0004
+/// use termcolor::Color;
+///
+/// fn name(color: Color) -> &'static str {
+///     match color {
+///         Color::Black => "black",
+///         Color::Blue => "blue",
+///         Color::Green => "green",
+///         Color::Red => "red",
+///         Color::Cyan => "cyan",
+///         Color::Magenta => "magenta",
+///         Color::Yellow => "yellow",
+///         Color::White => "white",
+///         Color::Ansi256(_) => "ansi256",
+///         Color::Rgb(..) => "rgb",
+///         Color::Default => "default",
+///     }
+/// }
+/// ```
+///
+/// This type has a `FromStr` impl that can parse colors from their human
+/// readable form. The format is as follows:
+///
+/// 1. Any of the explicitly listed colors in English. They are matched
+///    case insensitively.
+/// 2. A single 8-bit integer, in either decimal or hexadecimal format. It
+///    may also be written as `ansi256(N)` or `ansi256:N`.
+/// 3. A triple of 8-bit integers separated by a comma, where each integer is
+///    in decimal or hexadecimal format. It may also be written as
+///    `rgb(R,G,B)`.
+/// 4. A CSS style hex color, either `#RRGGBB` or `#RGB`.
+/// 5. An xterm style color, `rgb:R/G/B`, where each component is written
+///    with 1 to 4 hexadecimal digits and scaled to 8 bits. For example,
+///    `rgb:ff/80/00`.
+///
+/// Hexadecimal numbers are written with a `0x` prefix, except in the CSS and
+/// xterm formats. Whitespace around the color and around each number is
+/// ignored, as is the case of the prefixes.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Color {
+    Black,
+    Blue,
+    Green,
+    Red,
+    Cyan,
+    Magenta,
+    Yellow,
+    White,
+    Ansi256(u8),
+    Rgb(u8, u8, u8),
+    /// The terminal's default foreground or background color.
+    ///
+    /// Unlike `WriteColor::reset`, which clears all colors and styles, this
+    /// only restores the default for the foreground or background color to
+    /// which it is applied. For example, with the `Ansi` writer, a foreground
+    /// color of `Default` emits `\x1B[39m` while leaving the background and
+    /// any styles (such as bold) in place. Note that `ColorSpec` resets
+    /// everything before applying its colors unless `set_reset(false)` is
+    /// used.
+    ///
+    /// This is not supported by the Windows console and is silently ignored
+    /// there.
+    Default,
+}
+
+impl Color {
+    /// Translate this color to a wincon::Color.
+    #[cfg(windows)]
+    fn to_windows(
+        self,
+        intense: bool,
+    ) -> Option<(wincon::Intense, wincon::Color)> {
+        use wincon::Intense::{No, Yes};
+
+        let color = match self {
+            Color::Black => wincon::Color::Black,
+            Color::Blue => wincon::Color::Blue,
+            Color::Green => wincon::Color::Green,
+            Color::Red => wincon::Color::Red,
+            Color::Cyan => wincon::Color::Cyan,
+            Color::Magenta => wincon::Color::Magenta,
+            Color::Yellow => wincon::Color::Yellow,
+            Color::White => wincon::Color::White,
+            Color::Ansi256(0) => return Some((No, wincon::Color::Black)),
+            Color::Ansi256(1) => return Some((No, wincon::Color::Red)),
+            Color::Ansi256(2) => return Some((No, wincon::Color::Green)),
+            Color::Ansi256(3) => return Some((No, wincon::Color::Yellow)),
+            Color::Ansi256(4) => return Some((No, wincon::Color::Blue)),
+            Color::Ansi256(5) => return Some((No, wincon::Color::Magenta)),
+            Color::Ansi256(6) => return Some((No, wincon::Color::Cyan)),
+            Color::Ansi256(7) => return Some((No, wincon::Color::White)),
+            Color::Ansi256(8) => return Some((Yes, wincon::Color::Black)),
+            Color::Ansi256(9) => return Some((Yes, wincon::Color::Red)),
+            Color::Ansi256(10) => return Some((Yes, wincon::Color::Green)),
+            Color::Ansi256(11) => return Some((Yes, wincon::Color::Yellow)),
+            Color::Ansi256(12) => return Some((Yes, wincon::Color::Blue)),
+            Color::Ansi256(13) => return Some((Yes, wincon::Color::Magenta)),
+            Color::Ansi256(14) => return Some((Yes, wincon::Color::Cyan)),
+            Color::Ansi256(15) => return Some((Yes, wincon::Color::White)),
+            Color::Ansi256(_) => return None,
+            Color::Rgb(_, _, _) => return None,
+            Color::Default => return None,
+        };
+        let intense = if intense { Yes } else { No };
+        Some((intense, color))
+    }
+
+    /// Returns the closest of the 16 basic colors to this color.
+    ///
+    /// Named colors are returned unchanged. `Ansi256` colors `0` through `7`
+    /// are returned as their corresponding named colors, while `8` through
+    /// `15` (the intense variants) are returned unchanged. For all other
+    /// colors, the basic color with the minimum Euclidean distance in RGB
+    /// space is returned, using the default xterm palette.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::Red.nearest_16(), Color::Red);
+    /// assert_eq!(Color::Ansi256(4).nearest_16(), Color::Blue);
+    /// assert_eq!(Color::Ansi256(9).nearest_16(), Color::Ansi256(9));
+    /// assert_eq!(Color::Rgb(0, 200, 10).nearest_16(), Color::Green);
+    /// ```
+    pub fn nearest_16(self) -> Color {
+        match self {
+            Color::Ansi256(n) if n < 16 => Color::from_basic_index(n),
+            Color::Ansi256(n) => {
+                Color::from_basic_index(rgb_to_basic(ansi256_to_rgb(n)))
+            }
+            Color::Rgb(r, g, b) => {
+                Color::from_basic_index(rgb_to_basic((r, g, b)))
+            }
+            c => c,
+        }
+    }
+
+    /// Returns the index of the closest color in the xterm 256 color palette.
+    ///
+    /// Named colors return their standard index, `0` through `7`, and
+    /// `Ansi256` colors return their index unchanged. For `Rgb` colors, the
+    /// color with the minimum Euclidean distance in RGB space is chosen from
+    /// the 6x6x6 color cube (indices `16` through `231`) and the grayscale
+    /// ramp (indices `232` through `255`). The first 16 colors are not
+    /// searched since terminal themes commonly redefine them.
+    ///
+    /// Since `Color::Default` refers to whatever color the terminal uses by
+    /// default, it has no palette index and `None` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::Magenta.nearest_256_index(), Some(5));
+    /// assert_eq!(Color::Ansi256(200).nearest_256_index(), Some(200));
+    /// assert_eq!(Color::Rgb(255, 0, 0).nearest_256_index(), Some(196));
+    /// assert_eq!(Color::Default.nearest_256_index(), None);
+    /// ```
+    pub fn nearest_256_index(self) -> Option<u8> {
+        Some(match self {
+            Color::Black => 0,
+            Color::Red => 1,
+            Color::Green => 2,
+            Color::Yellow => 3,
+            Color::Blue => 4,
+            Color::Magenta => 5,
+            Color::Cyan => 6,
+            Color::White => 7,
+            Color::Ansi256(n) => n,
+            Color::Rgb(r, g, b) => rgb_to_ansi256((r, g, b)),
+            Color::Default => return None,
+        })
+    }
+
+    /// Returns the complement of this color.
+    ///
+    /// The named colors are paired up as Black and White, Red and Cyan,
+    /// Green and Magenta, and Blue and Yellow. For `Rgb` colors, each channel
+    /// is inverted. For `Ansi256` colors, the RGB value of the color in the
+    /// default xterm palette is inverted and the closest `Ansi256` color to
+    /// the result (as determined by [`Color::nearest_256_index`]) is
+    /// returned. `Color::Default` is returned unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::Red.complement(), Color::Cyan);
+    /// assert_eq!(Color::Rgb(255, 0, 10).complement(), Color::Rgb(0, 255, 245));
+    /// assert_eq!(Color::Ansi256(16).complement(), Color::Ansi256(231));
+    /// ```
+    pub fn complement(self) -> Color {
+        match self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+            Color::Red => Color::Cyan,
+            Color::Cyan => Color::Red,
+            Color::Green => Color::Magenta,
+            Color::Magenta => Color::Green,
+            Color::Blue => Color::Yellow,
+            Color::Yellow => Color::Blue,
+            Color::Ansi256(n) => {
+                let (r, g, b) = ansi256_to_rgb(n);
+                Color::Ansi256(rgb_to_ansi256((255 - r, 255 - g, 255 - b)))
+            }
+            Color::Rgb(r, g, b) => Color::Rgb(255 - r, 255 - g, 255 - b),
+            Color::Default => Color::Default,
+        }
+    }
+
+    /// Create an RGB color from the given hue, saturation and lightness.
+    ///
+    /// The hue is in degrees in the range `0.0..=360.0`, while saturation and
+    /// lightness are in the range `0.0..=1.0`. Values outside of these ranges
+    /// are clamped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::from_hsl(0.0, 1.0, 0.5), Color::Rgb(255, 0, 0));
+    /// assert_eq!(Color::from_hsl(210.0, 0.5, 0.4), Color::Rgb(51, 102, 153));
+    /// ```
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Color {
+        let h = hue.clamp(0.0, 360.0) % 360.0;
+        let s = saturation.clamp(0.0, 1.0);
+        let l = lightness.clamp(0.0, 1.0);
+
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let m = l - c / 2.0;
+        let (r, g, b) = match h {
+            h if h < 60.0 => (c, x, 0.0),
+            h if h < 120.0 => (x, c, 0.0),
+            h if h < 180.0 => (0.0, c, x),
+            h if h < 240.0 => (0.0, x, c),
+            h if h < 300.0 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        // The float to int casts saturate, so rounding errors can't overflow.
+        let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+        Color::Rgb(channel(r), channel(g), channel(b))
+    }
+
+    /// Returns the hue, saturation and lightness of this color.
+    ///
+    /// The hue is in degrees in the range `0.0..360.0`, while saturation and
+    /// lightness are in the range `0.0..=1.0`. Achromatic colors have a hue
+    /// and saturation of `0.0`.
+    ///
+    /// This returns `None` for colors whose RGB value depends on the
+    /// terminal's theme, which includes the named colors, `Color::Default`
+    /// and `Ansi256` colors `0` through `15`. The other `Ansi256` colors use
+    /// their RGB value in the xterm 256 color palette.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::Rgb(0, 255, 0).to_hsl(), Some((120.0, 1.0, 0.5)));
+    /// assert_eq!(Color::Red.to_hsl(), None);
+    /// ```
+    pub fn to_hsl(self) -> Option<(f32, f32, f32)> {
+        let (r, g, b) = match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Ansi256(n) if n >= 16 => ansi256_to_rgb(n),
+            _ => return None,
+        };
+        let (r, g, b) =
+            (f32::from(r) / 255.0, f32::from(g) / 255.0, f32::from(b) / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let d = max - min;
+        let l = (max + min) / 2.0;
+        if d == 0.0 {
+            return Some((0.0, 0.0, l));
+        }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * ((g - b) / d).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / d + 2.0)
+        } else {
+            60.0 * ((r - g) / d + 4.0)
+        };
+        Some((h, s, l))
+    }
+
+    /// Create an RGB color from a packed `0x00RRGGBB` integer.
+    ///
+    /// The upper byte is ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::from_u32(0x00FF0000), Color::Rgb(255, 0, 0));
+    /// assert_eq!(Color::from_u32(0xFF336699), Color::Rgb(0x33, 0x66, 0x99));
+    /// ```
+    pub fn from_u32(rgb: u32) -> Color {
+        let [_, r, g, b] = rgb.to_be_bytes();
+        Color::Rgb(r, g, b)
+    }
+
+    /// Returns this color packed into an integer as `0x00RRGGBB`.
+    ///
+    /// Like [`Color::to_hsl`], this returns `None` for colors whose RGB value
+    /// depends on the terminal's theme, which includes the named colors,
+    /// `Color::Default` and `Ansi256` colors `0` through `15`. The other
+    /// `Ansi256` colors use their RGB value in the xterm 256 color palette.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use termcolor::Color;
+    ///
+    /// assert_eq!(Color::Rgb(255, 0, 0).to_u32(), Some(0x00FF0000));
+    /// assert_eq!(Color::Ansi256(196).to_u32(), Some(0x00FF0000));
+    /// assert_eq!(Color::Red.to_u32(), None);
+    /// ```
+    pub fn to_u32(self) -> Option<u32> {
+        let (r, g, b) = match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Ansi256(n) if n >= 16 => ansi256_to_rgb(n),
+            _ => return None,
+        };
+        Some(u32::from_be_bytes([0, r, g, b]))
+    }
+
+    /// Returns this color as a CSS hex color, using the default xterm palette
+    /// for named and `Ansi256` colors.
+    ///
+    /// This returns `None` for `Color::Default`, since it has no fixed value.
+    fn to_css(self, intense: bool) -> Option<String> {
+        let (r, g, b) = match self {
+            Color::Ansi256(n) => ansi256_to_rgb(n),
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Default => return None,
+            named => {
+                let n = named.nearest_256_index()?;
+                ansi256_to_rgb(if intense { n + 8 } else { n })
+            }
+        };
+        Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+    }
+
+    /// Map this color to the closest color supported at the given level.
+    ///
+    /// This returns `None` when no colors are supported.
+    fn to_level(self, level: ColorLevel) -> Option<Color> {
+        Some(match (level, self) {
+            (ColorLevel::None, _) => return None,
+            (ColorLevel::TrueColor, c) => c,
+            (ColorLevel::Ansi256, Color::Rgb(r, g, b)) => {
+                Color::Ansi256(rgb_to_ansi256((r, g, b)))
+            }
+            (ColorLevel::Ansi256, c) => c,
+            (ColorLevel::Basic, Color::Ansi256(n)) if n < 16 => self,
+            (ColorLevel::Basic, c) => c.nearest_16(),
+        })
+    }
+
+    /// Returns the color for an index into the 16 basic colors.
+    ///
+    /// The first 8 indices correspond to the named colors, while the rest
+    /// are returned as `Ansi256` colors.
+    fn from_basic_index(n: u8) -> Color {
+        match n {
+            0 => Color::Black,
+            1 => Color::Red,
+            2 => Color::Green,
+            3 => Color::Yellow,
+            4 => Color::Blue,
+            5 => Color::Magenta,
+            6 => Color::Cyan,
+            7 => Color::White,
+            n => Color::Ansi256(n),
+        }
+    }
+
+    /// Parses a color written in one of the function-like, CSS or xterm
+    /// formats, returning `None` if `s` isn't written in any of them.
+    fn from_str_extended(s: &str) -> Option<Result<Color, ParseColorError>> {
+        fn call<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+            s.strip_prefix(name)?
+                .trim_start()
+                .strip_prefix('(')?
+                .strip_suffix(')')
+        }
+
+        fn hex_digit(b: u8) -> Option<u8> {
+            (b as char).to_digit(16).map(|d| d as u8)
+        }
+
+        fn css(hex: &str) -> Option<Color> {
+            let d: Vec<u8> =
+                hex.bytes().map(hex_digit).collect::<Option<_>>()?;
+            match d.len() {
+                3 => Some(Color::Rgb(d[0] * 17, d[1] * 17, d[2] * 17)),
+                6 => Some(Color::Rgb(
+                    d[0] * 16 + d[1],
+                    d[2] * 16 + d[3],
+                    d[4] * 16 + d[5],
+                )),
+                _ => None,
+            }
+        }
+
+        fn xterm_component(hex: &str) -> Option<u8> {
+            let hex = hex.trim();
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let max = (1u32 << (4 * hex.len())) - 1;
+            Some(((value * 255 + max / 2) / max) as u8)
+        }
+
+        fn triple(
+            s: &str,
+            sep: char,
+            component: fn(&str) -> Option<u8>,
+        ) -> Option<Color> {
+            let mut parts = s.split(sep).map(component);
+            let color =
+                Color::Rgb(parts.next()??, parts.next()??, parts.next()??);
+            if parts.next().is_some() {
+                return None;
+            }
+            Some(color)
+        }
+
+        let lower = s.to_lowercase();
+        let (color, kind) = if let Some(hex) = lower.strip_prefix('#') {
+            (css(hex.trim()), ParseColorErrorKind::InvalidRgb)
+        } else if let Some(rgb) = lower.strip_prefix("rgb:") {
+            (
+                triple(rgb, '/', xterm_component),
+                ParseColorErrorKind::InvalidRgb,
+            )
+        } else if let Some(args) = call(&lower, "rgb") {
+            (
+                triple(args, ',', parse_color_number),
+                ParseColorErrorKind::InvalidRgb,
+            )
+        } else if let Some(n) =
+            call(&lower, "ansi256").or_else(|| lower.strip_prefix("ansi256:"))
+        {
+            (
+                parse_color_number(n).map(Color::Ansi256),
+                ParseColorErrorKind::InvalidAnsi256,
+            )
+        } else {
+            return None;
+        };
+        Some(
+            color
+                .ok_or_else(|| ParseColorError { kind, given: s.to_string() }),
+        )
+    }
+
+    /// Parses a numeric color string, either ANSI or RGB.
+    fn from_str_numeric(s: &str) -> Result<Color, ParseColorError> {
+        // The "ansi256" format is a single number (decimal or hex)
+        // corresponding to one of 256 colors.
+        //
+        // The "rgb" format is a triple of numbers (decimal or hex) delimited
+        // by a comma corresponding to one of 256^3 colors.
+
+        let codes: Vec<&str> = s.split(',').collect();
+        if codes.len() == 1 {
+            if let Some(n) = parse_color_number(codes[0]) {
+                Ok(Color::Ansi256(n))
+            } else {
+                if s.chars().all(|c| c.is_ascii_hexdigit()) {
+                    Err(ParseColorError {
+                        kind: ParseColorErrorKind::InvalidAnsi256,
+                        given: s.to_string(),
+                    })
+                } else {
+                    Err(ParseColorError {
+                        kind: ParseColorErrorKind::InvalidName,
+                        given: s.to_string(),
+                    })
+                }
+            }
+        } else if codes.len() == 3 {
+            let mut v = vec![];
+            for code in codes {
+                let n = parse_color_number(code).ok_or_else(|| {
+                    ParseColorError {
+                        kind: ParseColorErrorKind::InvalidRgb,
+                        given: s.to_string(),
+                    }
+                })?;
+                v.push(n);
+            }
+            Ok(Color::Rgb(v[0], v[1], v[2]))
+        } else {
+            Err(if s.contains(",") {
+                ParseColorError {
+                    kind: ParseColorErrorKind::InvalidRgb,
+                    given: s.to_string(),
+                }
+            } else {
+                ParseColorError {
+                    kind: ParseColorErrorKind::InvalidName,
+                    given: s.to_string(),
+                }
+            })
+        }
+    }
+}
+
+/// Parses an 8-bit integer written in either decimal or hexadecimal (with a
+/// `0x` prefix), ignoring surrounding whitespace.
+fn parse_color_number(s: &str) -> Option<u8> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x") {
+        u8::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse::<u8>().ok()
+    }
+}
+
+/// An error from parsing an invalid color specification.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseColorError {
+    kind: ParseColorErrorKind,
+    given: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ParseColorErrorKind {
+    InvalidName,
+    InvalidAnsi256,
+    InvalidRgb,
+    InvalidSpec,
+}
+
+impl ParseColorError {
+    /// Return the string that couldn't be parsed as a valid color.
+    pub fn invalid(&self) -> &str {
+        &self.given
+    }
+}
+
+impl error::Error for ParseColorError {
+    fn description(&self) -> &str {
+        use self::ParseColorErrorKind::*;
+        match self.kind {
+            InvalidName => "unrecognized color name",
+            InvalidAnsi256 => "invalid ansi256 color number",
+            InvalidRgb => "invalid RGB color triple",
+            InvalidSpec => "invalid color specification",
+        }
+    }
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ParseColorErrorKind::*;
+        match self.kind {
+            InvalidName => write!(
+                f,
+                "unrecognized color name '{}'. Choose from: \
+                 black, blue, green, red, cyan, magenta, yellow, \
+                 white, default, or use an ansi256 color number \
+                 ('N', 'ansi256(N)' or 'ansi256:N') or an RGB color \
+                 ('R,G,B', 'rgb(R,G,B)', '#RRGGBB', '#RGB' or \
+                 'rgb:R/G/B')",
+                self.given
+            ),
+            InvalidAnsi256 => write!(
+                f,
+                "unrecognized ansi256 color number, \
+                 should be '[0-255]' (or a hex number), optionally \
+                 written as 'ansi256(N)' or 'ansi256:N', but is '{}'",
+                self.given
+            ),
+            InvalidRgb => write!(
+                f,
+                "unrecognized RGB color, should be \
+                 '[0-255],[0-255],[0-255]' (or a hex triple), \
+                 'rgb(R,G,B)', '#RRGGBB', '#RGB' or 'rgb:R/G/B' (with \
+                 1-4 hex digits per component), but is '{}'",
+                self.given
+            ),
+            InvalidSpec => write!(
+                f,
+                "unrecognized color specification '{}', should be \
+                 'fg:COLOR', 'bg:COLOR' or one of: bold, dimmed, intense, \
+                 italic, strikethrough, underline",
+                self.given
+            ),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Color, ParseColorError> {
+        let s = s.trim();
+        match &*s.to_lowercase() {
+            "black" => Ok(Color::Black),
+            "blue" => Ok(Color::Blue),
+            "green" => Ok(Color::Green),
+            "red" => Ok(Color::Red),
+            "cyan" => Ok(Color::Cyan),
+            "magenta" => Ok(Color::Magenta),
+            "yellow" => Ok(Color::Yellow),
+            "white" => Ok(Color::White),
+            "default" => Ok(Color::Default),
+            _ => Color::from_str_extended(s)
+                .unwrap_or_else(|| Color::from_str_numeric(s)),
+        }
+    }
+}
+
+/// Parses a color specification from a whitespace separated list of settings.
+///
+/// Each setting is one of `fg:COLOR`, `bg:COLOR`, `bold`, `dimmed`,
+/// `intense`, `italic`, `strikethrough` or `underline`, where `COLOR` is
+/// parsed with the `FromStr` impl for [`Color`]. Settings are matched case
+/// insensitively, and an empty string parses to an empty specification.
+///
+/// # Example
+///
+/// ```
+/// use termcolor::{Color, ColorSpec};
+///
+/// let spec: ColorSpec = "fg:red bg:0x10 bold".parse()?;
+/// assert_eq!(spec.fg(), Some(&Color::Red));
+/// assert_eq!(spec.bg(), Some(&Color::Ansi256(16)));
+/// assert!(spec.bold());
+/// # Ok::<(), termcolor::ParseColorError>(())
+/// ```
+impl FromStr for ColorSpec {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<ColorSpec, ParseColorError> {
+        let mut spec = ColorSpec::new();
+        for setting in s.split_whitespace() {
+            let lower = setting.to_lowercase();
+            // The prefixes are ASCII, so slicing the original setting after
+            // matching them case insensitively is fine.
+            if lower.starts_with("fg:") {
+                spec.set_fg(Some(setting[3..].parse()?));
+                continue;
+            }
+            if lower.starts_with("bg:") {
+                spec.set_bg(Some(setting[3..].parse()?));
+                continue;
+            }
+            match &*lower {
+                "bold" => spec.set_bold(true),
+                "dimmed" => spec.set_dimmed(true),
+                "intense" => spec.set_intense(true),
+                "italic" => spec.set_italic(true),
+                "strikethrough" => spec.set_strikethrough(true),
+                "underline" => spec.set_underline(true),
+                _ => {
+                    return Err(ParseColorError {
+                        kind: ParseColorErrorKind::InvalidSpec,
+                        given: setting.to_string(),
+                    })
+                }
+            };
+        }
+        Ok(spec)
+    }
+}
+
+/// A mapping from the eight named colors to replacement colors.
+///
+/// A palette can be set on an [`Ansi`] writer via [`Ansi::set_palette`] to
+/// change how named colors are rendered without changing the colors used by
+/// callers. Named colors without a replacement are written as usual.
+///
+/// # Example
+///
+/// ```
+/// use termcolor::{Color, Palette};
+///
+/// let mut palette = Palette::new();
+/// palette.set(Color::Red, Some(Color::Rgb(0xD7, 0, 0)));
+/// assert_eq!(palette.get(Color::Red), Some(Color::Rgb(0xD7, 0, 0)));
+/// assert_eq!(palette.get(Color::Blue), None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Palette {
+    colors: [Option<Color>; 8],
+}
+
+impl Palette {
+    /// Create a new palette that doesn't replace any colors.
+    pub fn new() -> Palette {
+        Palette::default()
+    }
+
+    /// Returns the replacement for the given color, if one is set.
+    ///
+    /// This always returns `None` if the given color isn't one of the eight
+    /// named colors.
+    pub fn get(&self, color: Color) -> Option<Color> {
+        Palette::index(color).and_then(|i| self.colors[i])
+    }
+
+    /// Set the replacement for the given named color. A replacement of
+    /// `None` removes any existing replacement.
+    ///
+    /// Replacements are not applied recursively. That is, if a named color
+    /// is replaced with another named color, then the second named color is
+    /// written as is.
+    ///
+    /// This has no effect if the given color isn't one of the eight named
+    /// colors.
+    pub fn set(
+        &mut self,
+        color: Color,
+        replacement: Option<Color>,
+    ) -> &mut Palette {
+        if let Some(i) = Palette::index(color) {
+            self.colors[i] = replacement;
+        }
+        self
+    }
+
+    fn index(color: Color) -> Option<usize> {
+        match color {
+            Color::Black => Some(0),
+            Color::Red => Some(1),
+            Color::Green => Some(2),
+            Color::Yellow => Some(3),
+            Color::Blue => Some(4),
+            Color::Magenta => Some(5),
+            Color::Cyan => Some(6),
+            Color::White => Some(7),
+            _ => None,
+        }
+    }
+}
+
+/// The range of colors that a terminal supports.
+///
+/// This is used to degrade colors to ones that a terminal can display. See
+/// [`ColorSpec::to_lossy`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ColorLevel {
+    /// No colors at all.
+    ///
+    /// Colors are dropped entirely, while styles (such as bold) are kept.
+    None,
+    /// The 16 basic colors, i.e., the 8 named colors and their intense
+    /// variants.
+    ///
+    /// At this level, the `Ansi` writer only emits the 16 color codes
+    /// (`30-37`, `90-97` and their background counterparts) and never the
+    /// 256 color or 24-bit color sequences.
+    Basic,
+    /// The 256 colors of the xterm palette.
+    Ansi256,
+    /// Arbitrary 24-bit RGB colors.
+    TrueColor,
+}
+
+impl ColorLevel {
+    /// Guess the range of colors supported by the current terminal from the
+    /// environment.
+    ///
+    /// If `COLORTERM` is `truecolor` or `24bit`, or if `TERM` ends with
+    /// `-direct` or `-truecolor`, then `TrueColor` is returned. Otherwise, if
+    /// `TERM` ends with `-256color`, then `Ansi256` is returned. If `TERM` is
+    /// `dumb`, then `ColorLevel::None` is returned to indicate that colors
+    /// shouldn't be used at all. Any other `TERM` value results in `Basic`.
+    ///
+    /// When `TERM` isn't set, `ColorLevel::None` is returned, except on
+    /// Windows, where `Basic` is returned since `TERM` is rarely set there.
+    ///
+    /// The level returned can be given directly to
+    /// [`Ansi::set_color_level`]. Note that this only reflects the
+    /// capabilities of the terminal. It doesn't account for user preferences
+    /// such as `NO_COLOR`, which are handled by [`ColorChoice`].
+    pub fn from_env() -> ColorLevel {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        ColorLevel::from_term(env::var("TERM").ok().as_deref(), &colorterm)
+    }
+
+    /// Guess the range of colors supported by a terminal from the values of
+    /// its `TERM` and `COLORTERM` environment variables.
+    fn from_term(term: Option<&str>, colorterm: &str) -> ColorLevel {
+        let term = match term {
+            Some(term) => term,
+            None if cfg!(windows) => return ColorLevel::Basic,
+            None => return ColorLevel::None,
+        };
+        if term_is_dumb(term.as_ref()) {
+            return ColorLevel::None;
+        }
+        if colorterm == "truecolor"
+            || colorterm == "24bit"
+            || term.ends_with("-direct")
+            || term.ends_with("-truecolor")
+        {
+            ColorLevel::TrueColor
+        } else if term.ends_with("-256color") {
+            ColorLevel::Ansi256
+        } else {
+            ColorLevel::Basic
+        }
+    }
+}
+
+/// The RGB values of the 16 basic colors in the default xterm palette.
+const XTERM_BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The intensity of each channel in the 6x6x6 color cube of the xterm 256
+/// color palette.
+const XTERM_CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Returns the RGB value of the given color in the xterm 256 color palette.
+fn ansi256_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => XTERM_BASIC_RGB[usize::from(n)],
+        16..=231 => {
+            let n = usize::from(n - 16);
+            (
+                XTERM_CUBE_LEVELS[n / 36],
+                XTERM_CUBE_LEVELS[(n / 6) % 6],
+                XTERM_CUBE_LEVELS[n % 6],
+            )
+        }
+        _ => {
+            let gray = 8 + 10 * (n - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Returns the index of the color in the xterm 256 color palette that is
+/// closest to the given RGB value.
+///
+/// Only the color cube and the grayscale ramp are searched, since the first
+/// 16 colors are commonly redefined by terminal themes.
+fn rgb_to_ansi256(rgb: (u8, u8, u8)) -> u8 {
+    fn nearest_level(c: u8) -> usize {
+        (0..XTERM_CUBE_LEVELS.len())
+            .min_by_key(|&i| {
+                (i32::from(XTERM_CUBE_LEVELS[i]) - i32::from(c)).abs()
+            })
+            .unwrap()
+    }
+
+    let (r, g, b) =
+        (nearest_level(rgb.0), nearest_level(rgb.1), nearest_level(rgb.2));
+    // The cube index is at most 231, so the cast is lossless.
+    let cube = (16 + 36 * r + 6 * g + b) as u8;
+    let gray = (232..=255u8)
+        .min_by_key(|&n| rgb_distance(rgb, ansi256_to_rgb(n)))
+        .unwrap();
+    if rgb_distance(rgb, ansi256_to_rgb(gray))
+        < rgb_distance(rgb, ansi256_to_rgb(cube))
+    {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// Returns the index of the basic color closest to the given RGB value.
+fn rgb_to_basic(rgb: (u8, u8, u8)) -> u8 {
+    (0..16u8).min_by_key(|&n| rgb_distance(rgb, ansi256_to_rgb(n))).unwrap()
+}
+
+/// Returns the squared Euclidean distance between two RGB values.
+fn rgb_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| {
+        let d = i32::from(x) - i32::from(y);
+        (d * d) as u32
+    };
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// A hyperlink specification.
+#[derive(Clone, Debug)]
+pub struct HyperlinkSpec<'a> {
+    uri: Option<&'a [u8]>,
+}
+
+impl<'a> HyperlinkSpec<'a> {
+    /// Creates a new hyperlink specification.
+    pub fn open(uri: &'a [u8]) -> HyperlinkSpec<'a> {
+        HyperlinkSpec { uri: Some(uri) }
+    }
+
+    /// Creates a hyperlink specification representing no hyperlink.
+    pub fn close() -> HyperlinkSpec<'a> {
+        HyperlinkSpec { uri: None }
+    }
+
+    /// Returns the URI of the hyperlink if one is attached to this spec.
+    pub fn uri(&self) -> Option<&'a [u8]> {
+        self.uri
+    }
+}
+
+/// Percent-encodes the bytes in a hyperlink URI that would otherwise break
+/// the escape sequence it's embedded in.
+///
+/// This encodes all ASCII control characters (`0x00` through `0x1F`, and
+/// `0x7F`), which includes the BEL and `ESC` bytes used to terminate OSC 8
+/// sequences. All other bytes are left as is. If the URI contains no such
+/// bytes, then it is returned without copying.
+///
+/// The writers in this crate call this on every hyperlink URI before writing
+/// it, so callers only need this when writing hyperlinks themselves.
+///
+/// # Example
+///
+/// ```
+/// use termcolor::sanitize_hyperlink_uri;
+///
+/// assert_eq!(
+///     &*sanitize_hyperlink_uri(b"https://example.com/\x1B\\x"),
+///     b"https://example.com/%1B\\x",
+/// );
+/// ```
+pub fn sanitize_hyperlink_uri(uri: &[u8]) -> Cow<'_, [u8]> {
+    fn needs_encoding(b: u8) -> bool {
+        b.is_ascii_control()
+    }
+
+    if !uri.iter().any(|&b| needs_encoding(b)) {
+        return Cow::Borrowed(uri);
+    }
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut sanitized = Vec::with_capacity(uri.len() + 8);
+    for &b in uri {
+        if needs_encoding(b) {
+            sanitized.push(b'%');
+            sanitized.push(HEX[usize::from(b >> 4)]);
+            sanitized.push(HEX[usize::from(b & 0xF)]);
+        } else {
+            sanitized.push(b);
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+/// The portion of the current line to erase.
+///
+/// This is used with [`Ansi::erase_line`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EraseLineMode {
+    /// Erase from the cursor to the end of the line.
+    ToEnd,
+    /// Erase from the start of the line to the cursor.
+    ToStart,
+    /// Erase the entire line.
+    All,
+}
+
+/// The portion of the display to erase.
+///
+/// This is used with [`Ansi::erase_display`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EraseDisplayMode {
+    /// Erase from the cursor to the end of the display.
+    ToEnd,
+    /// Erase from the start of the display to the cursor.
+    ToStart,
+    /// Erase the entire display.
+    All,
+    /// Erase the entire display along with the terminal's scrollback
+    /// buffer.
+    ///
+    /// Not all terminals support erasing the scrollback buffer.
+    AllAndScrollback,
+}
+
+/// The shape of the cursor and whether it blinks.
+///
+/// This is used with [`Ansi::set_cursor_style`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CursorStyle {
+    /// The cursor style configured by the user.
+    DefaultUserShape,
+    /// A blinking block.
+    BlinkingBlock,
+    /// A block that doesn't blink.
+    SteadyBlock,
+    /// A blinking underline.
+    BlinkingUnderline,
+    /// An underline that doesn't blink.
+    SteadyUnderline,
+    /// A blinking vertical bar.
+    BlinkingBar,
+    /// A vertical bar that doesn't blink.
+    SteadyBar,
+}
+
+/// Returns the number of bytes in the given text that are not part of an
+/// ANSI escape sequence.
+///
+/// This skips control sequences (such as the SGR sequences used to set
+/// colors) and operating system commands (such as OSC 8 hyperlinks,
+/// terminated by either BEL or ST). An incomplete escape sequence at the end
+/// of the input is treated as non-printing.
+///
+/// Note that this counts bytes, not characters. Callers that need the width
+/// of the text in a terminal must account for multi-byte and wide
+/// characters themselves.
+///
+/// # Example
+///
+/// ```
+/// use termcolor::visible_len;
+///
+/// assert_eq!(3, visible_len(b"\x1B[0m\x1B[31mfoo\x1B[0m"));
+/// ```
+pub fn visible_len(ansi_bytes: &[u8]) -> usize {
+    AnsiSegments::new(ansi_bytes)
+        .map(|seg| match seg {
+            AnsiSegment::Text(text) => text.len(),
+            AnsiSegment::Escape(_) => 0,
+        })
+        .sum()
+}
+
+/// A piece of ANSI-formatted text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AnsiSegment<'a> {
+    /// Text that is printed as is.
+    Text(&'a [u8]),
+    /// An escape sequence, including its leading `ESC` byte. This may be
+    /// incomplete if it occurs at the end of the input.
+    Escape(&'a [u8]),
+}
+
+/// An iterator that splits ANSI-formatted text into printable text and
+/// escape sequences.
+#[derive(Clone, Debug)]
+struct AnsiSegments<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> AnsiSegments<'a> {
+    fn new(bytes: &'a [u8]) -> AnsiSegments<'a> {
+        AnsiSegments { bytes }
+    }
+
+    /// Returns the length of the escape sequence at the start of `bytes`,
+    /// which must begin with an `ESC` byte.
+    fn escape_len(bytes: &[u8]) -> usize {
+        match bytes.get(1) {
+            None => 1,
+            // A control sequence ends with a byte in the range 0x40-0x7E.
+            Some(&b'[') => bytes[2..]
+                .iter()
+                .position(|&b| (0x40..=0x7E).contains(&b))
+                .map_or(bytes.len(), |i| i + 3),
+            // An operating system command ends with either BEL or ST.
+            Some(&b']') => {
+                let mut i = 2;
+                while i < bytes.len() {
+                    if bytes[i] == b'\x07' {
+                        return i + 1;
+                    }
+                    if bytes[i] == b'\x1B' && bytes.get(i + 1) == Some(&b'\\')
+                    {
+                        return i + 2;
+                    }
+                    i += 1;
+                }
+                bytes.len()
+            }
+            Some(_) => 2,
+        }
+    }
+}
+
+impl<'a> Iterator for AnsiSegments<'a> {
+    type Item = AnsiSegment<'a>;
+
+    fn next(&mut self) -> Option<AnsiSegment<'a>> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let (seg, len) = if self.bytes[0] == b'\x1B' {
+            let len = AnsiSegments::escape_len(self.bytes);
+            (AnsiSegment::Escape(&self.bytes[..len]), len)
+        } else {
+            let len = self
+                .bytes
+                .iter()
+                .position(|&b| b == b'\x1B')
+                .unwrap_or(self.bytes.len());
+            (AnsiSegment::Text(&self.bytes[..len]), len)
+        };
+        self.bytes = &self.bytes[len..];
+        Some(seg)
+    }
+}
+
+/// A piece of ANSI-formatted text, interpreted by [`AnsiEvents`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum AnsiEvent<'a> {
+    /// Text that is printed as is.
+    Text(&'a [u8]),
+    /// A change in color settings. The spec always has `reset` set, since it
+    /// describes every setting in effect. `None` means that no settings are
+    /// in effect.
+    Color(Option<ColorSpec>),
+    /// The start of an OSC 8 hyperlink with the given parameters and URI, or
+    /// the end of one when the URI is absent. The parameters are kept as
+    /// written, e.g., `id=1:foo=bar`.
+    Hyperlink(&'a [u8], Option<&'a [u8]>),
+    /// Any other escape sequence.
+    Escape(&'a [u8]),
+}
+
+/// An iterator that interprets the escape sequences in ANSI-formatted text.
+///
+/// Consecutive SGR sequences are combined into a single `AnsiEvent::Color`,
+/// which is reported just before the next event of any other kind.
+#[derive(Clone, Debug)]
+struct AnsiEvents<'a> {
+    segments: AnsiSegments<'a>,
+    /// The color settings in effect.
+    spec: ColorSpec,
+    /// Whether `spec` has changed since the last color event.
+    dirty: bool,
+    /// An event found while reporting a color event, to be reported next.
+    pending: Option<AnsiEvent<'a>>,
+}
+
+impl<'a> AnsiEvents<'a> {
+    fn new(bytes: &'a [u8]) -> AnsiEvents<'a> {
+        AnsiEvents {
+            segments: AnsiSegments::new(bytes),
+            spec: ColorSpec::new(),
+            dirty: false,
+            pending: None,
+        }
+    }
+
+    /// Returns the event for the current color settings.
+    fn color_event(&self) -> AnsiEvent<'a> {
+        if self.spec == ColorSpec::new() {
+            AnsiEvent::Color(None)
+        } else {
+            AnsiEvent::Color(Some(self.spec.clone()))
+        }
+    }
+
+    /// Returns the parameters of the given escape sequence if it's a
+    /// complete SGR sequence.
+    fn sgr_params(esc: &[u8]) -> Option<&[u8]> {
+        esc.strip_prefix(b"\x1B[")?.strip_suffix(b"m")
+    }
+
+    /// Returns the parameters and URI of the given escape sequence if it's a
+    /// complete OSC 8 hyperlink sequence. An empty URI closes the hyperlink.
+    fn hyperlink(esc: &[u8]) -> Option<(&[u8], Option<&[u8]>)> {
+        let body = esc.strip_prefix(b"\x1B]8;")?;
+        let body = body
+            .strip_suffix(b"\x1B\\")
+            .or_else(|| body.strip_suffix(b"\x07"))?;
+        let sep = body.iter().position(|&b| b == b';')?;
+        let (params, uri) = (&body[..sep], &body[sep + 1..]);
+        Some((params, if uri.is_empty() { None } else { Some(uri) }))
+    }
+
+    /// Splits the parameters of an OSC 8 hyperlink sequence into `key=value`
+    /// pairs, in the form accepted by `WriteColor::set_hyperlink_with`.
+    ///
+    /// This returns `None` if the parameters aren't valid UTF-8 or if any of
+    /// them isn't a `key=value` pair.
+    fn hyperlink_params(params: &[u8]) -> Option<Vec<(&str, &str)>> {
+        let params = std::str::from_utf8(params).ok()?;
+        if params.is_empty() {
+            return Some(vec![]);
+        }
+        params.split(':').map(|param| param.split_once('=')).collect()
+    }
+}
+
+impl<'a> Iterator for AnsiEvents<'a> {
+    type Item = AnsiEvent<'a>;
+
+    fn next(&mut self) -> Option<AnsiEvent<'a>> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+        loop {
+            let event = match self.segments.next() {
+                None => None,
+                Some(AnsiSegment::Text(text)) => Some(AnsiEvent::Text(text)),
+                Some(AnsiSegment::Escape(esc)) => {
+                    if let Some(params) = AnsiEvents::sgr_params(esc) {
+                        apply_sgr(&mut self.spec, params);
+                        self.dirty = true;
+                        continue;
+                    }
+                    Some(match AnsiEvents::hyperlink(esc) {
+                        Some((params, uri)) => {
+                            AnsiEvent::Hyperlink(params, uri)
+                        }
+                        None => AnsiEvent::Escape(esc),
+                    })
+                }
+            };
+            if self.dirty {
+                self.dirty = false;
+                self.pending = event;
+                return Some(self.color_event());
+            }
+            return event;
+        }
+    }
+}
+
+/// Applies the parameters of an SGR escape sequence to the given spec.
+///
+/// This understands the sequences written by [`Ansi`], along with the
+/// sequences that turn off individual styles. Unrecognized parameters are
+/// ignored.
+fn apply_sgr(spec: &mut ColorSpec, params: &[u8]) {
+    fn number(param: &[u8]) -> Option<u16> {
+        if param.is_empty() {
+            return Some(0);
+        }
+        std::str::from_utf8(param).ok()?.parse().ok()
+    }
+
+    fn extended<'a, I: Iterator<Item = &'a [u8]>>(
+        params: &mut I,
+    ) -> Option<Color> {
+        let mut channel = || {
+            params
+                .next()
+                .and_then(number)
+                .filter(|&n| n <= 255)
+                .map(|n| n as u8)
+        };
+        match channel()? {
+            5 => Some(Color::Ansi256(channel()?)),
+            2 => Some(Color::Rgb(channel()?, channel()?, channel()?)),
+            _ => None,
+        }
+    }
+
+    let mut params = params.split(|&b| b == b';');
+    while let Some(param) = params.next() {
+        let mut parts = param.split(|&b| b == b':');
+        let code = match parts.next().and_then(number) {
+            None => continue,
+            Some(code) => code,
+        };
+        match code {
+            0 => *spec = ColorSpec::new(),
+            1 => spec.bold = true,
+            2 => spec.dimmed = true,
+            3 => spec.italic = true,
+            4 => {
+                let style = match parts.next().and_then(number) {
+                    None | Some(1) => UnderlineStyle::Straight,
+                    Some(0) => {
+                        spec.underline = false;
+                        spec.underline_style = UnderlineStyle::Straight;
+                        continue;
+                    }
+                    Some(2) => UnderlineStyle::Double,
+                    Some(3) => UnderlineStyle::Curly,
+                    Some(4) => UnderlineStyle::Dotted,
+                    Some(5) => UnderlineStyle::Dashed,
+                    Some(_) => continue,
+                };
+                spec.underline = true;
+                spec.underline_style = style;
+            }
+            9 => spec.strikethrough = true,
+            22 => {
+                spec.bold = false;
+                spec.dimmed = false;
+            }
+            23 => spec.italic = false,
+            24 => {
+                spec.underline = false;
+                spec.underline_style = UnderlineStyle::Straight;
+            }
+            29 => spec.strikethrough = false,
+            30..=37 => {
+                spec.fg_color =
+                    Some(Color::from_basic_index((code - 30) as u8))
+            }
+            38 => {
+                if let Some(c) = extended(&mut params) {
+                    spec.fg_color = Some(c);
+                }
+            }
+            39 => spec.fg_color = Some(Color::Default),
+            40..=47 => {
+                spec.bg_color =
+                    Some(Color::from_basic_index((code - 40) as u8))
+            }
+            48 => {
+                if let Some(c) = extended(&mut params) {
+                    spec.bg_color = Some(c);
+                }
+            }
+            49 => spec.bg_color = Some(Color::Default),
+            90..=97 => spec.fg_color = Some(Color::Ansi256((code - 82) as u8)),
+            100..=107 => {
+                spec.bg_color = Some(Color::Ansi256((code - 92) as u8))
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LossyStandardStream<W> {
+    wtr: W,
+    #[cfg(windows)]
+    is_console: bool,
+    #[cfg(windows)]
+    lossy: bool,
+}
+
+impl<W: io::Write> LossyStandardStream<W> {
+    #[cfg(not(windows))]
+    fn new(wtr: W) -> LossyStandardStream<W> {
+        LossyStandardStream { wtr }
+    }
+
+    #[cfg(windows)]
+    fn new(wtr: W) -> LossyStandardStream<W> {
+        let is_console = wincon::Console::stdout().is_ok()
+            || wincon::Console::stderr().is_ok();
+        LossyStandardStream { wtr, is_console, lossy: true }
+    }
+
+    /// Create a new stream that never performs lossy conversion.
+    #[cfg(not(windows))]
+    fn passthrough(wtr: W) -> LossyStandardStream<W> {
+        LossyStandardStream { wtr }
+    }
+
+    /// Create a new stream that never performs lossy conversion.
+    #[cfg(windows)]
+    fn passthrough(wtr: W) -> LossyStandardStream<W> {
+        LossyStandardStream { wtr, is_console: false, lossy: false }
+    }
+
+    #[cfg(not(windows))]
+    fn wrap<Q: io::Write>(&self, wtr: Q) -> LossyStandardStream<Q> {
+        LossyStandardStream::new(wtr)
+    }
+
+    #[cfg(windows)]
+    fn wrap<Q: io::Write>(&self, wtr: Q) -> LossyStandardStream<Q> {
+        LossyStandardStream {
+            wtr,
+            is_console: self.is_console,
+            lossy: self.lossy,
+        }
+    }
+
+    /// Like `wrap`, but consumes this stream and builds the new stream by
+    /// applying `f` to the underlying writer.
+    #[cfg(not(windows))]
+    fn map<Q: io::Write>(
+        self,
+        f: impl FnOnce(W) -> Q,
+    ) -> LossyStandardStream<Q> {
+        LossyStandardStream::new(f(self.wtr))
+    }
+
+    /// Like `wrap`, but consumes this stream and builds the new stream by
+    /// applying `f` to the underlying writer.
+    #[cfg(windows)]
+    fn map<Q: io::Write>(
+        self,
+        f: impl FnOnce(W) -> Q,
+    ) -> LossyStandardStream<Q> {
+        LossyStandardStream {
+            wtr: f(self.wtr),
+            is_console: self.is_console,
+            lossy: self.lossy,
+        }
+    }
+
+    /// Set whether invalid UTF-8 is replaced when writing to a console.
+    #[cfg(not(windows))]
+    fn set_lossy(&mut self, _yes: bool) {}
+
+    /// Set whether invalid UTF-8 is replaced when writing to a console.
+    #[cfg(windows)]
+    fn set_lossy(&mut self, yes: bool) {
+        self.lossy = yes;
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.wtr
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.wtr
+    }
+
+    fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+impl<W: WriteColor> WriteColor for LossyStandardStream<W> {
+    fn supports_color(&self) -> bool {
+        self.wtr.supports_color()
+    }
+    fn supports_hyperlinks(&self) -> bool {
+        self.wtr.supports_hyperlinks()
+    }
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.wtr.set_color(spec)
+    }
+    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
+        self.wtr.set_hyperlink(link)
+    }
+    fn set_hyperlink_with(
+        &mut self,
+        params: &[(&str, &str)],
+        uri: &[u8],
+    ) -> io::Result<()> {
+        self.wtr.set_hyperlink_with(params, uri)
+    }
+    fn reset(&mut self) -> io::Result<()> {
+        self.wtr.reset()
+    }
+    fn is_synchronous(&self) -> bool {
+        self.wtr.is_synchronous()
+    }
+}
+
+impl<W: io::Write> io::Write for LossyStandardStream<W> {
+    #[cfg(not(windows))]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.wtr.write(buf)
+    }
+
+    #[cfg(windows)]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_console && self.lossy {
+            write_lossy_utf8(&mut self.wtr, buf)
+        } else {
+            self.wtr.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(windows)]
+fn write_lossy_utf8<W: io::Write>(mut w: W, buf: &[u8]) -> io::Result<usize> {
+    match ::std::str::from_utf8(buf) {
+        Ok(s) => w.write(s.as_bytes()),
+        Err(ref e) if e.valid_up_to() == 0 => {
+            w.write_all(b"\xEF\xBF\xBD")?;
+            Ok(1)
+        }
+        Err(e) => w.write(&buf[..e.valid_up_to()]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::io;
+
+    use super::{
+        apply_sgr, sanitize_hyperlink_uri, term_is_dumb, visible_len,
+        AlternateScreen, Ansi, AnsiEvent, AnsiEvents, AnsiSegment,
+        AnsiSegments, AttributeKind, Attributes, Buffer, BufferInner,
+        BufferWriter, Color, ColorChoice, ColorLevel, ColorSpec,
+        ColorSpecMask, CursorStyle, EraseDisplayMode, EraseLineMode,
+        HiddenCursor, Html, HyperlinkSpec, Indent, IoStandardStream,
+        LineColor, LossyStandardStream, NoColor, Op, Palette, ParseColorError,
+        ParseColorErrorKind, PooledBuffer, Record, SavedCursor, Sink,
+        StandardStream, StandardStreamBuilder, StandardStreamKind,
+        SynchronizedUpdate, UnderlineStyle, WriteColor, WriterInner,
+        BUFFER_POOL, DEFAULT_POOL_SIZE,
+    };
+
+    fn assert_is_send<T: Send>() {}
+
+    #[test]
+    fn standard_stream_is_send() {
+        assert_is_send::<StandardStream>();
+    }
+
+    #[test]
+    fn test_simple_parse_ok() {
+        let color = "green".parse::<Color>();
+        assert_eq!(color, Ok(Color::Green));
+    }
+
+    #[test]
+    fn test_256_parse_ok() {
+        let color = "7".parse::<Color>();
+        assert_eq!(color, Ok(Color::Ansi256(7)));
+
+        let color = "32".parse::<Color>();
+        assert_eq!(color, Ok(Color::Ansi256(32)));
+
+        let color = "0xFF".parse::<Color>();
+        assert_eq!(color, Ok(Color::Ansi256(0xFF)));
+    }
+
+    #[test]
+    fn test_256_parse_err_out_of_range() {
+        let color = "256".parse::<Color>();
+        assert_eq!(
+            color,
+            Err(ParseColorError {
+                kind: ParseColorErrorKind::InvalidAnsi256,
+                given: "256".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_rgb_parse_ok() {
+        let color = "0,0,0".parse::<Color>();
+        assert_eq!(color, Ok(Color::Rgb(0, 0, 0)));
+
+        let color = "0,128,255".parse::<Color>();
+        assert_eq!(color, Ok(Color::Rgb(0, 128, 255)));
+
+        let color = "0x0,0x0,0x0".parse::<Color>();
+        assert_eq!(color, Ok(Color::Rgb(0, 0, 0)));
+
+        let color = "0x33,0x66,0xFF".parse::<Color>();
+        assert_eq!(color, Ok(Color::Rgb(0x33, 0x66, 0xFF)));
+    }
+
+    #[test]
+    fn test_rgb_parse_err_out_of_range() {
+        let color = "0,0,256".parse::<Color>();
+        assert_eq!(
+            color,
+            Err(ParseColorError {
+                kind: ParseColorErrorKind::InvalidRgb,
+                given: "0,0,256".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_rgb_parse_err_bad_format() {
+        let color = "0,0".parse::<Color>();
+        assert_eq!(
+            color,
+            Err(ParseColorError {
+                kind: ParseColorErrorKind::InvalidRgb,
+                given: "0,0".to_string(),
+            })
+        );
+
+        let color = "not_a_color".parse::<Color>();
+        assert_eq!(
+            color,
+            Err(ParseColorError {
+                kind: ParseColorErrorKind::InvalidName,
+                given: "not_a_color".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_var_ansi_write_rgb() {
+        let mut buf = Ansi::new(vec![]);
+        let _ = buf.write_color(true, &Color::Rgb(254, 253, 255), false);
+        assert_eq!(buf.wtr, b"\x1B[38;2;254;253;255m");
+    }
+
+    #[test]
+    fn test_reset() {
+        let spec = ColorSpec::new();
+        let mut buf = Ansi::new(vec![]);
+        buf.set_color(&spec).unwrap();
+        assert_eq!(buf.wtr, b"\x1B[0m");
+    }
+
+    #[test]
+    fn test_no_reset() {
+        let mut spec = ColorSpec::new();
+        spec.set_reset(false);
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_color(&spec).unwrap();
+        assert_eq!(buf.wtr, b"");
+    }
+
+    #[test]
+    fn test_var_ansi_write_256() {
+        let mut buf = Ansi::new(vec![]);
+        let _ = buf.write_color(false, &Color::Ansi256(7), false);
+        assert_eq!(buf.wtr, b"\x1B[48;5;7m");
+
+        let mut buf = Ansi::new(vec![]);
+        let _ = buf.write_color(false, &Color::Ansi256(208), false);
+        assert_eq!(buf.wtr, b"\x1B[48;5;208m");
+    }
+
+    fn all_attributes() -> Vec<ColorSpec> {
+        let mut result = vec![];
+        for fg in [None, Some(Color::Red)] {
+            for bg in [None, Some(Color::Red)] {
+                for bold in [false, true] {
+                    for underline in [false, true] {
+                        for intense in [false, true] {
+                            for italic in [false, true] {
+                                for strikethrough in [false, true] {
+                                    for dimmed in [false, true] {
+                                        let mut color = ColorSpec::new();
+                                        color.set_fg(fg);
+                                        color.set_bg(bg);
+                                        color.set_bold(bold);
+                                        color.set_underline(underline);
+                                        color.set_intense(intense);
+                                        color.set_italic(italic);
+                                        color.set_dimmed(dimmed);
+                                        color.set_strikethrough(strikethrough);
+                                        result.push(color);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_is_none() {
+        for (i, color) in all_attributes().iter().enumerate() {
+            assert_eq!(
+                i == 0,
+                color.is_none(),
+                "{:?} => {}",
+                color,
+                color.is_none()
+            )
+        }
+    }
+
+    #[test]
+    fn test_clear() {
+        for color in all_attributes() {
+            let mut color1 = color.clone();
+            color1.clear();
+            assert!(color1.is_none(), "{:?} => {:?}", color, color1);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_console_bold_is_intense() {
+        use winapi_util::console::{Color as WinColor, Intense};
+
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red)).set_bg(Some(Color::Blue));
+        assert!(!spec.console_fg_intense(true));
+
+        spec.set_bold(true);
+        // Bold is only approximated when the writer opts in.
+        assert!(!spec.console_fg_intense(false));
+        assert!(spec.console_fg_intense(true));
+        assert_eq!(
+            Some((Intense::Yes, WinColor::Red)),
+            Color::Red.to_windows(spec.console_fg_intense(true)),
+        );
+        // Bold never makes the background intense.
+        assert_eq!(
+            Some((Intense::No, WinColor::Blue)),
+            Color::Blue.to_windows(spec.intense()),
+        );
+
+        spec.set_bold(false).set_intense(true);
+        assert!(spec.console_fg_intense(false));
+    }
+
+    #[test]
+    fn test_active_attributes() {
+        for color in all_attributes() {
+            assert_eq!(color.is_none(), color.active_attributes().is_empty());
+        }
+
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red))
+            .set_bg(Some(Color::Ansi256(7)))
+            .set_italic(true)
+            .set_bold(true);
+        assert_eq!(
+            spec.active_attributes(),
+            vec![
+                AttributeKind::Fg(Color::Red),
+                AttributeKind::Bg(Color::Ansi256(7)),
+                AttributeKind::Bold,
+                AttributeKind::Italic,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_lossy() {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Rgb(255, 100, 50)))
+            .set_bg(Some(Color::Ansi256(208)))
+            .set_bold(true);
+
+        let none = spec.to_lossy(ColorLevel::None);
+        assert_eq!(none.fg(), None);
+        assert_eq!(none.bg(), None);
+        assert!(none.bold());
+
+        let basic = spec.to_lossy(ColorLevel::Basic);
+        assert_eq!(basic.fg(), Some(&Color::Ansi256(9)));
+        assert_eq!(basic.bg(), Some(&Color::Yellow));
+        assert!(basic.bold());
+
+        let ansi256 = spec.to_lossy(ColorLevel::Ansi256);
+        assert_eq!(ansi256.fg(), Some(&Color::Ansi256(203)));
+        assert_eq!(ansi256.bg(), Some(&Color::Ansi256(208)));
+        assert!(ansi256.bold());
+
+        assert_eq!(spec.to_lossy(ColorLevel::TrueColor), spec);
+
+        // Colors that are already representable are never changed.
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Blue)).set_bg(Some(Color::Ansi256(12)));
+        assert_eq!(spec.to_lossy(ColorLevel::Basic), spec);
+        assert_eq!(spec.to_lossy(ColorLevel::Ansi256), spec);
+
+        // Grays prefer the grayscale ramp over the color cube.
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Rgb(128, 128, 128)));
+        let ansi256 = spec.to_lossy(ColorLevel::Ansi256);
+        assert_eq!(ansi256.fg(), Some(&Color::Ansi256(244)));
+        let basic = spec.to_lossy(ColorLevel::Basic);
+        assert_eq!(basic.fg(), Some(&Color::Ansi256(8)));
+    }
+
+    #[test]
+    fn test_ansi_hyperlink() {
+        let mut buf = Ansi::new(vec![]);
+        buf.set_hyperlink(&HyperlinkSpec::open(b"https://example.com"))
+            .unwrap();
+        buf.write_str("label").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+
+        assert_eq!(
+            buf.wtr,
+            b"\x1B]8;;https://example.com\x1B\\label\x1B]8;;\x1B\\".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_ansi_erase() {
+        let mut buf = Ansi::new(vec![]);
+        buf.erase_line(EraseLineMode::ToEnd).unwrap();
+        buf.erase_line(EraseLineMode::ToStart).unwrap();
+        buf.erase_line(EraseLineMode::All).unwrap();
+        buf.clear_line().unwrap();
+        assert_eq!(buf.wtr, b"\x1B[0K\x1B[1K\x1B[2K\x1B[2K");
+
+        let mut buf = Ansi::new(vec![]);
+        buf.erase_display(EraseDisplayMode::ToEnd).unwrap();
+        buf.erase_display(EraseDisplayMode::ToStart).unwrap();
+        buf.erase_display(EraseDisplayMode::All).unwrap();
+        buf.erase_display(EraseDisplayMode::AllAndScrollback).unwrap();
+        assert_eq!(buf.wtr, b"\x1B[0J\x1B[1J\x1B[2J\x1B[3J");
+    }
+
+    #[test]
+    fn test_ansi_saved_cursor() {
+        let mut buf = Ansi::new(vec![]);
+        buf.save_cursor().unwrap();
+        buf.restore_cursor().unwrap();
+        assert_eq!(buf.wtr, b"\x1B7\x1B8");
+    }
+
+    #[cfg(feature = "env-cache")]
+    #[test]
+    fn test_env_cache() {
+        use std::env;
+
+        // No other test reads the environment through `ColorChoice::Auto`,
+        // so modifying it here shouldn't interfere with anything else.
+        let old_term = env::var_os("TERM");
+        let old_no_color = env::var_os("NO_COLOR");
+
+        env::set_var("TERM", "xterm");
+        env::remove_var("NO_COLOR");
+        ColorChoice::refresh_env();
+        assert!(ColorChoice::Auto.should_attempt_color());
+
+        // The cached decision is used until the cache is refreshed.
+        env::set_var("NO_COLOR", "1");
+        assert!(ColorChoice::Auto.should_attempt_color());
+        ColorChoice::refresh_env();
+        assert!(!ColorChoice::Auto.should_attempt_color());
+
+        // Always and Never never consult the cache.
+        assert!(ColorChoice::Always.should_attempt_color());
+        assert!(!ColorChoice::Never.should_attempt_color());
+
+        // TERM=cygwin doesn't disable colors on any platform.
+        env::set_var("TERM", "cygwin");
+        env::remove_var("NO_COLOR");
+        ColorChoice::refresh_env();
+        assert!(ColorChoice::Auto.should_attempt_color());
+
+        match old_term {
+            None => env::remove_var("TERM"),
+            Some(v) => env::set_var("TERM", v),
+        }
+        match old_no_color {
+            None => env::remove_var("NO_COLOR"),
+            Some(v) => env::set_var("NO_COLOR", v),
+        }
+        ColorChoice::refresh_env();
+    }
+
+    #[test]
+    fn test_ansi_flush_on_reset() {
+        #[derive(Debug, Default)]
+        struct CountFlushes {
+            buf: Vec<u8>,
+            flushes: usize,
+        }
+
+        impl io::Write for CountFlushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.buf.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushes += 1;
+                Ok(())
+            }
+        }
+
+        let mut buf = Ansi::new(CountFlushes::default());
+        buf.reset().unwrap();
+        assert_eq!(buf.get_ref().flushes, 0);
+
+        buf.flush_on_reset(true);
+        buf.set_color(ColorSpec::new().set_bold(true)).unwrap();
+        assert_eq!(buf.get_ref().flushes, 0);
+        buf.reset().unwrap();
+        assert_eq!(buf.get_ref().flushes, 1);
+        assert_eq!(buf.get_ref().buf, b"\x1B[0m\x1B[0m\x1B[1m\x1B[0m");
+    }
+
+    #[test]
+    fn test_ansi_segments() {
+        let segs: Vec<AnsiSegment> =
+            AnsiSegments::new(b"a\x1B[1;31mb\x1B]8;;x\x07c\x1B7\x1B[")
+                .collect();
+        assert_eq!(
+            segs,
+            vec![
+                AnsiSegment::Text(b"a"),
+                AnsiSegment::Escape(b"\x1B[1;31m"),
+                AnsiSegment::Text(b"b"),
+                AnsiSegment::Escape(b"\x1B]8;;x\x07"),
+                AnsiSegment::Text(b"c"),
+                AnsiSegment::Escape(b"\x1B7"),
+                AnsiSegment::Escape(b"\x1B["),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_visible_len() {
+        assert_eq!(0, visible_len(b""));
+        assert_eq!(3, visible_len(b"foo"));
+        assert_eq!(3, visible_len(b"\x1B[0m\x1B[31mfoo\x1B[0m"));
+        assert_eq!(
+            6,
+            visible_len(b"\x1B[1mfoo\x1B[38;2;1;2;3m\x1B[48;5;208mbar\x1B[0m")
+        );
+    }
+
+    #[test]
+    fn test_visible_len_nested() {
+        let mut buf = Ansi::new(vec![]);
+        buf.set_color(ColorSpec::new().set_fg(Some(Color::Red))).unwrap();
+        buf.write_str("a").unwrap();
+        buf.set_color(
+            ColorSpec::new()
+                .set_bg(Some(Color::Rgb(1, 2, 3)))
+                .set_reset(false),
+        )
+        .unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::open(b"https://example.com"))
+            .unwrap();
+        buf.write_str("bc").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        buf.reset().unwrap();
+        buf.write_str("d").unwrap();
+        assert_eq!(4, visible_len(&buf.wtr));
+    }
+
+    #[test]
+    fn test_visible_len_hyperlink_terminators() {
+        // OSC 8 terminated by ST.
+        assert_eq!(
+            5,
+            visible_len(b"\x1B]8;;https://a\x1B\\label\x1B]8;;\x1B\\")
+        );
+        // OSC 8 terminated by BEL.
+        assert_eq!(5, visible_len(b"\x1B]8;;https://a\x07label\x1B]8;;\x07"));
+    }
+
+    #[test]
+    fn test_visible_len_incomplete() {
+        assert_eq!(3, visible_len(b"foo\x1B"));
+        assert_eq!(3, visible_len(b"foo\x1B["));
+        assert_eq!(3, visible_len(b"foo\x1B[38;5;2"));
+        assert_eq!(3, visible_len(b"foo\x1B]8;;https://example.com"));
+        assert_eq!(3, visible_len(b"foo\x1B]8;;https://example.com\x1B"));
+    }
+
+    /// Returns a path in the temporary directory that is unique to this
+    /// process and the given name.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "termcolor-test-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_standard_stream_from_raw_fd() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
+
+        let path = temp_path("from-raw-fd");
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+        let mut stream =
+            unsafe { StandardStream::from_raw_fd(fd, ColorChoice::Always) };
+        stream.set_color(ColorSpec::new().set_fg(Some(Color::Red))).unwrap();
+        write!(stream, "foo").unwrap();
+        {
+            let mut lock = stream.lock();
+            lock.reset().unwrap();
+            write!(lock, "bar").unwrap();
+        }
+        drop(stream);
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, b"\x1B[0m\x1B[31mfoo\x1B[0mbar");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_standard_stream_into_inner() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
+
+        for &choice in &[ColorChoice::Always, ColorChoice::Never] {
+            let path = temp_path("into-inner");
+            let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+            let mut stream =
+                unsafe { StandardStream::from_raw_fd(fd, choice) };
+            stream
+                .set_color(ColorSpec::new().set_fg(Some(Color::Red)))
+                .unwrap();
+            write!(stream, "foo").unwrap();
+            let mut wtr = stream.into_inner();
+            write!(wtr, "bar").unwrap();
+            drop(wtr);
+
+            let contents = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            if choice == ColorChoice::Always {
+                assert_eq!(contents, b"\x1B[0m\x1B[31mfoobar");
+            } else {
+                assert_eq!(contents, b"foobar");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_standard_stream_from_raw_handle() {
+        use std::io::Write;
+        use std::os::windows::io::IntoRawHandle;
+
+        let path = temp_path("from-raw-handle");
+        let handle = std::fs::File::create(&path).unwrap().into_raw_handle();
+        let mut stream = unsafe {
+            StandardStream::from_raw_handle(handle, ColorChoice::Never)
+        };
+        stream.set_color(ColorSpec::new().set_fg(Some(Color::Red))).unwrap();
+        write!(stream, "foo").unwrap();
+        drop(stream);
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, b"foo");
+    }
+
+    #[test]
+    fn test_ansi_palette() {
+        let mut palette = Palette::new();
+        palette
+            .set(Color::Red, Some(Color::Rgb(0xD7, 0, 0)))
+            .set(Color::Blue, Some(Color::Ansi256(33)))
+            .set(Color::Green, Some(Color::Yellow))
+            .set(Color::Ansi256(1), Some(Color::White));
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_palette(Some(palette));
+        let _ = buf.write_color(true, &Color::Red, false);
+        let _ = buf.write_color(false, &Color::Blue, true);
+        let _ = buf.write_color(true, &Color::Green, true);
+        let _ = buf.write_color(true, &Color::Cyan, false);
+        let _ = buf.write_color(true, &Color::Ansi256(1), false);
+        assert_eq!(
+            buf.wtr,
+            b"\x1B[38;2;215;0;0m\x1B[48;5;33m\x1B[38;5;11m\x1B[36m\x1B[38;5;1m"
+        );
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_palette(Some(palette)).set_palette(None);
+        let _ = buf.write_color(true, &Color::Red, false);
+        assert_eq!(buf.wtr, b"\x1B[31m");
+    }
+
+    #[test]
+    fn test_eq_ignoring() {
+        let mut spec1 = ColorSpec::new();
+        spec1.set_fg(Some(Color::Red)).set_bg(Some(Color::Blue));
+        let mut spec2 = spec1.clone();
+        assert!(spec1.eq_ignoring(&spec2, ColorSpecMask::NONE));
+        assert!(spec1.eq_ignoring(&spec2, ColorSpecMask::ALL));
+
+        spec2.set_fg(Some(Color::Green));
+        assert!(!spec1.eq_ignoring(&spec2, ColorSpecMask::NONE));
+        assert!(!spec1.eq_ignoring(&spec2, ColorSpecMask::BG));
+        assert!(spec1.eq_ignoring(&spec2, ColorSpecMask::FG));
+
+        spec2.set_bg(None);
+        assert!(!spec1.eq_ignoring(&spec2, ColorSpecMask::FG));
+        assert!(spec1.eq_ignoring(&spec2, ColorSpecMask::COLORS));
+        assert!(
+            spec1.eq_ignoring(&spec2, ColorSpecMask::FG | ColorSpecMask::BG)
+        );
+
+        let mut spec2 = spec1.clone();
+        spec2.set_bold(true).set_italic(true).set_reset(false);
+        assert!(!spec1.eq_ignoring(&spec2, ColorSpecMask::BOLD));
+        assert!(!spec1.eq_ignoring(&spec2, ColorSpecMask::COLORS));
+        assert!(spec1.eq_ignoring(&spec2, ColorSpecMask::ATTRIBUTES));
+        let mut mask = ColorSpecMask::BOLD;
+        mask |= ColorSpecMask::ITALIC;
+        assert!(!spec1.eq_ignoring(&spec2, mask));
+        mask |= ColorSpecMask::RESET;
+        assert!(spec1.eq_ignoring(&spec2, mask));
+    }
+
+    #[test]
+    fn test_buffer_writer_on_print() {
+        use std::io::Write;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::{Arc, Mutex};
+
+        let path = temp_path("on_print");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut wtr = BufferWriter {
+            stream: LossyStandardStream::new(IoStandardStream::File(file)),
+            printed: AtomicBool::new(false),
+            separator: None,
+            color_choice: ColorChoice::Never,
+            color_level: ColorLevel::TrueColor,
+            hooks: Default::default(),
+            pool_size: DEFAULT_POOL_SIZE,
+            bold_as_intense: false,
+            #[cfg(windows)]
+            console: None,
+        };
+        let seen = Arc::new(Mutex::new(vec![]));
+        for i in 0..2 {
+            let seen = Arc::clone(&seen);
+            wtr.on_print(move |buf| {
+                seen.lock().unwrap().push((i, buf.as_slice().to_vec()));
+            });
+        }
+
+        let mut buf = wtr.buffer();
+        wtr.print(&buf).unwrap();
+        assert!(seen.lock().unwrap().is_empty());
+        buf.write_all(b"foo").unwrap();
+        wtr.print(&buf).unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(0, b"foo".to_vec()), (1, b"foo".to_vec())]
+        );
+
+        drop(wtr);
+        assert_eq!(std::fs::read(&path).unwrap(), b"foo");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_nearest_16() {
+        let named = [
+            Color::Black,
+            Color::Red,
+            Color::Green,
+            Color::Yellow,
+            Color::Blue,
+            Color::Magenta,
+            Color::Cyan,
+            Color::White,
+        ];
+        for (i, &c) in named.iter().enumerate() {
+            assert_eq!(c.nearest_16(), c);
+            assert_eq!(Color::Ansi256(i as u8).nearest_16(), c);
+        }
+        for n in 8..16 {
+            assert_eq!(Color::Ansi256(n).nearest_16(), Color::Ansi256(n));
+        }
+        assert_eq!(Color::Ansi256(196).nearest_16(), Color::Ansi256(9));
+        assert_eq!(Color::Ansi256(232).nearest_16(), Color::Black);
+        assert_eq!(Color::Ansi256(255).nearest_16(), Color::White);
+        assert_eq!(Color::Rgb(0, 0, 0).nearest_16(), Color::Black);
+        assert_eq!(Color::Rgb(200, 10, 10).nearest_16(), Color::Red);
+        assert_eq!(Color::Rgb(90, 90, 250).nearest_16(), Color::Ansi256(12));
+    }
+
+    #[test]
+    fn test_ansi_default_color() {
+        use std::io::Write;
+
+        let mut buf = Ansi::new(vec![]);
+        let _ = buf.write_color(true, &Color::Default, false);
+        let _ = buf.write_color(false, &Color::Default, true);
+        assert_eq!(buf.wtr, b"\x1B[39m\x1B[49m");
+
+        // Only the foreground is removed, so bold and the background remain.
+        let mut buf = Ansi::new(vec![]);
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red)).set_bg(Some(Color::Blue)).set_bold(true);
+        buf.set_color(&spec).unwrap();
+        buf.write_all(b"a").unwrap();
+        let mut spec = ColorSpec::new();
+        spec.set_reset(false).set_fg(Some(Color::Default));
+        buf.set_color(&spec).unwrap();
+        buf.write_all(b"b").unwrap();
+        assert_eq!(
+            buf.wtr,
+            b"\x1B[0m\x1B[1m\x1B[31m\x1B[44ma\x1B[39mb".to_vec()
+        );
+
+        assert_eq!("default".parse(), Ok(Color::Default));
+    }
+
+    #[test]
+    fn test_nearest_256_index() {
+        let named = [
+            Color::Black,
+            Color::Red,
+            Color::Green,
+            Color::Yellow,
+            Color::Blue,
+            Color::Magenta,
+            Color::Cyan,
+            Color::White,
+        ];
+        for (i, &c) in named.iter().enumerate() {
+            assert_eq!(c.nearest_256_index(), Some(i as u8));
+        }
+        for n in 0..=255 {
+            assert_eq!(Color::Ansi256(n).nearest_256_index(), Some(n));
+        }
+        assert_eq!(Color::Rgb(0, 0, 0).nearest_256_index(), Some(16));
+        assert_eq!(Color::Rgb(255, 255, 255).nearest_256_index(), Some(231));
+        assert_eq!(Color::Rgb(0, 95, 135).nearest_256_index(), Some(24));
+        assert_eq!(Color::Rgb(100, 100, 100).nearest_256_index(), Some(241));
+        assert_eq!(Color::Rgb(90, 100, 140).nearest_256_index(), Some(60));
+        assert_eq!(Color::Default.nearest_256_index(), None);
+    }
+
+    #[test]
+    fn test_line_color() {
+        use std::io::Write;
+
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red));
+        let mut wtr = LineColor::new(Ansi::new(vec![]), spec);
+        wtr.write_all(b"fo").unwrap();
+        wtr.write_all(b"o\nba").unwrap();
+        wtr.write_all(b"r\n\nqu").unwrap();
+        wtr.write_all(b"ux").unwrap();
+        assert_eq!(
+            wtr.get_ref().get_ref(),
+            b"\x1B[0m\x1B[31mfoo\x1B[0m\n\
+              \x1B[0m\x1B[31mbar\x1B[0m\n\
+              \n\
+              \x1B[0m\x1B[31mquux"
+        );
+
+        // A trailing partial line is only closed by a reset.
+        wtr.reset().unwrap();
+        wtr.write_all(b"!").unwrap();
+        assert!(wtr
+            .into_inner()
+            .into_inner()
+            .ends_with(b"\x1B[0m\x1B[31mquux\x1B[0m\x1B[0m\x1B[31m!"));
+    }
+
+    #[test]
+    fn test_indent() {
+        use std::io::Write;
+
+        let mut wtr = Indent::new(Ansi::new(vec![]), "> ");
+        wtr.write_all(b"fo").unwrap();
+        wtr.write_all(b"o\nba").unwrap();
+        wtr.write_all(b"r\n\nquux\n").unwrap();
+        assert_eq!(wtr.get_ref().get_ref(), b"> foo\n> bar\n\n> quux\n");
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+        let mut blue = ColorSpec::new();
+        blue.set_fg(Some(Color::Blue));
+        let mut wtr = Indent::new(Ansi::new(vec![]), "|");
+        wtr.set_indent_color(Some(blue.clone()));
+        wtr.write_all(b"a\n").unwrap();
+        wtr.set_color(&red).unwrap();
+        wtr.write_all(b"b\nc").unwrap();
+        wtr.reset().unwrap();
+        assert_eq!(
+            wtr.get_ref().get_ref(),
+            b"\x1B[0m\x1B[34m|\x1B[0ma\n\
+              \x1B[0m\x1B[31m\
+              \x1B[0m\x1B[34m|\x1B[0m\x1B[31mb\n\
+              \x1B[0m\x1B[34m|\x1B[0m\x1B[31mc\x1B[0m"
+        );
+
+        // Nested indents each write their own indent, in their own colors,
+        // and the body keeps its color.
+        let outer = Indent::new(NoColor::new(vec![]), "  ");
+        let mut wtr = Indent::new(outer, "- ");
+        wtr.set_color(&red).unwrap();
+        wtr.write_all(b"foo\nb").unwrap();
+        wtr.write_all(b"ar\n").unwrap();
+        assert_eq!(wtr.get_ref().get_ref().get_ref(), b"  - foo\n  - bar\n");
+
+        let mut outer = Indent::new(Ansi::new(vec![]), "  ");
+        outer.set_indent_color(Some(blue));
+        let mut wtr = Indent::new(outer, "*");
+        wtr.set_color(&red).unwrap();
+        wtr.write_all(b"x\n").unwrap();
+        assert_eq!(
+            wtr.into_inner().into_inner().into_inner(),
+            b"\x1B[0m\x1B[31m\x1B[0m\
+              \x1B[0m\x1B[34m  \x1B[0m\
+              *\x1B[0m\x1B[31mx\n"
+        );
+    }
+
+    #[test]
+    fn test_ansi_title() {
+        let mut buf = Ansi::new(vec![]);
+        buf.set_title("foo").unwrap();
+        buf.set_window_title("ŝnaŭ ☃").unwrap();
+        assert_eq!(buf.wtr, "\x1B]0;foo\x1B\\\x1B]2;ŝnaŭ ☃\x1B\\".as_bytes());
+
+        let mut buf = Ansi::new(vec![]);
+        for title in &["a\x1B\\\x1B[2Jb", "\x07", "a\nb", "\u{9C}"] {
+            let err = buf.set_title(title).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let err = buf.set_window_title(title).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(buf.wtr.is_empty());
+    }
+
+    #[test]
+    fn test_color_spec_parse() {
+        let spec: ColorSpec = "".parse().unwrap();
+        assert_eq!(spec, ColorSpec::new());
+
+        let spec: ColorSpec =
+            " FG:Red  bg:255,0,0x10 bold\tunderline ".parse().unwrap();
+        let mut expected = ColorSpec::new();
+        expected
+            .set_fg(Some(Color::Red))
+            .set_bg(Some(Color::Rgb(255, 0, 16)))
+            .set_bold(true)
+            .set_underline(true);
+        assert_eq!(spec, expected);
+
+        let err = "fg:red blink".parse::<ColorSpec>().unwrap_err();
+        assert_eq!(
+            err,
+            ParseColorError {
+                kind: ParseColorErrorKind::InvalidSpec,
+                given: "blink".to_string(),
+            }
+        );
+        let err = "fg:Purple".parse::<ColorSpec>().unwrap_err();
+        assert_eq!(err.kind, ParseColorErrorKind::InvalidName);
+        assert_eq!(err.invalid(), "Purple");
+    }
+
+    #[test]
+    fn test_color_spec_from_env() {
+        let var = "TERMCOLOR_TEST_COLOR_SPEC_FROM_ENV";
+        std::env::remove_var(var);
+        assert_eq!(ColorSpec::from_env(var), None);
+
+        std::env::set_var(var, "fg:blue italic");
+        let mut expected = ColorSpec::new();
+        expected.set_fg(Some(Color::Blue)).set_italic(true);
+        assert_eq!(ColorSpec::from_env(var), Some(Ok(expected)));
+
+        std::env::set_var(var, "fg:blue wat");
+        assert!(matches!(ColorSpec::from_env(var), Some(Err(_))));
+        std::env::remove_var(var);
+    }
+
+    #[test]
+    fn test_buffer_into_lossy_string() {
+        use std::io::Write;
+
+        let mut buf = Buffer::no_color();
+        buf.write_all(b"foo \xFF bar").unwrap();
+        assert_eq!(buf.into_lossy_string(), "foo \u{FFFD} bar");
+
+        let mut buf = Buffer::ansi();
+        buf.set_color(ColorSpec::new().set_fg(Some(Color::Red))).unwrap();
+        buf.write_all("☃".as_bytes()).unwrap();
+        assert_eq!(buf.into_lossy_string(), "\x1B[0m\x1B[31m☃");
+    }
+
+    #[test]
+    fn test_color_level_from_term() {
+        let level = ColorLevel::from_term;
+        assert_eq!(level(Some("dumb"), ""), ColorLevel::None);
+        assert_eq!(level(Some("dumb"), "truecolor"), ColorLevel::None);
+        assert_eq!(level(Some("xterm"), ""), ColorLevel::Basic);
+        assert_eq!(level(Some("xterm-256color"), ""), ColorLevel::Ansi256);
+        assert_eq!(level(Some("xterm-direct"), ""), ColorLevel::TrueColor);
+        assert_eq!(level(Some("xterm"), "24bit"), ColorLevel::TrueColor);
+        assert_eq!(level(Some("xterm"), "truecolor"), ColorLevel::TrueColor);
+        let unset =
+            if cfg!(windows) { ColorLevel::Basic } else { ColorLevel::None };
+        assert_eq!(level(None, ""), unset);
+    }
+
+    #[test]
+    fn test_ansi_color_level() {
+        let write = |level, c: Color, intense| {
+            let mut buf = Ansi::new(vec![]);
+            buf.set_color_level(level);
+            buf.write_color(true, &c, intense).unwrap();
+            buf.write_color(false, &c, intense).unwrap();
+            String::from_utf8(buf.into_inner()).unwrap()
+        };
+        let orange = Color::Rgb(255, 135, 0);
+
+        assert_eq!(
+            write(ColorLevel::TrueColor, orange, false),
+            "\x1B[38;2;255;135;0m\x1B[48;2;255;135;0m"
+        );
+        assert_eq!(
+            write(ColorLevel::Ansi256, orange, false),
+            "\x1B[38;5;208m\x1B[48;5;208m"
+        );
+        assert_eq!(
+            write(ColorLevel::Basic, orange, false),
+            "\x1B[33m\x1B[43m"
+        );
+        assert_eq!(
+            write(ColorLevel::Basic, Color::Rgb(250, 10, 10), false),
+            "\x1B[91m\x1B[101m"
+        );
+        assert_eq!(
+            write(ColorLevel::Basic, Color::Rgb(0, 0, 200), false),
+            "\x1B[34m\x1B[44m"
+        );
+        assert_eq!(
+            write(ColorLevel::Basic, Color::Ansi256(208), false),
+            "\x1B[33m\x1B[43m"
+        );
+        assert_eq!(
+            write(ColorLevel::Basic, Color::Green, true),
+            "\x1B[92m\x1B[102m"
+        );
+        assert_eq!(
+            write(ColorLevel::Basic, Color::Default, true),
+            "\x1B[39m\x1B[49m"
+        );
+
+        // Named colors are unaffected at the other levels.
+        assert_eq!(
+            write(ColorLevel::Ansi256, Color::Green, true),
+            "\x1B[38;5;10m\x1B[48;5;10m"
+        );
+
+        // No colors are written at all without color support.
+        for &c in &[orange, Color::Ansi256(208), Color::Green, Color::Default]
+        {
+            assert_eq!(write(ColorLevel::None, c, false), "");
+            assert_eq!(write(ColorLevel::None, c, true), "");
+        }
+        let mut buf = Ansi::new(vec![]);
+        buf.set_color_level(ColorLevel::None);
+        buf.set_color(ColorSpec::new().set_fg(Some(orange)).set_bold(true))
+            .unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[0m\x1B[1m");
+
+        // The basic level never uses the 256 color or 24-bit sequences.
+        let colors = (0..=255).map(Color::Ansi256).chain(
+            (0..=255).step_by(15).map(|v| Color::Rgb(v, 255 - v, v / 2)),
+        );
+        for c in colors {
+            for &intense in &[false, true] {
+                let out = write(ColorLevel::Basic, c, intense);
+                assert!(
+                    !out.contains(";5;") && !out.contains(";2;"),
+                    "{}",
+                    out
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_color_complement() {
+        let pairs = [
+            (Color::Black, Color::White),
+            (Color::Red, Color::Cyan),
+            (Color::Green, Color::Magenta),
+            (Color::Blue, Color::Yellow),
+        ];
+        for &(c1, c2) in &pairs {
+            assert_eq!(c1.complement(), c2);
+            assert_eq!(c2.complement(), c1);
+        }
+        assert_eq!(
+            Color::Rgb(0, 128, 255).complement(),
+            Color::Rgb(255, 127, 0)
+        );
+        // 196 is (255, 0, 0) and 51 is (0, 255, 255).
+        assert_eq!(Color::Ansi256(196).complement(), Color::Ansi256(51));
+        // 244 is (128, 128, 128), so it's closest to its own complement.
+        assert_eq!(Color::Ansi256(244).complement(), Color::Ansi256(244));
+        // 232 is (8, 8, 8), whose complement is closest to white in the cube.
+        assert_eq!(Color::Ansi256(232).complement(), Color::Ansi256(231));
+        assert_eq!(Color::Default.complement(), Color::Default);
+    }
+
+    #[test]
+    fn test_buffer_writer_color_level() {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Rgb(255, 0, 0)));
+        let tests = [
+            (None, "\x1B[0m\x1B[38;2;255;0;0m"),
+            (Some(ColorLevel::TrueColor), "\x1B[0m\x1B[38;2;255;0;0m"),
+            (Some(ColorLevel::Ansi256), "\x1B[0m\x1B[38;5;196m"),
+            (Some(ColorLevel::Basic), "\x1B[0m\x1B[91m"),
+        ];
+        for &(level, expected) in &tests {
+            let mut wtr = BufferWriter::stdout(ColorChoice::AlwaysAnsi);
+            if let Some(level) = level {
+                wtr.set_color_level(level);
+            }
+            let mut buf = wtr.buffer();
+            buf.set_color(&spec).unwrap();
+            assert_eq!(buf.as_slice(), expected.as_bytes());
+        }
+
+        let mut wtr = BufferWriter::stdout(ColorChoice::Never);
+        wtr.set_color_level(ColorLevel::Basic);
+        let mut buf = wtr.buffer();
+        buf.set_color(&spec).unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_color_spec_clear_fg_bg() {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red)).set_bg(Some(Color::Blue)).set_bold(true);
+
+        spec.clear_fg().set_italic(true);
+        assert_eq!(spec.fg(), None);
+        assert_eq!(spec.bg(), Some(&Color::Blue));
+        assert!(spec.bold() && spec.italic());
+
+        spec.clear_bg();
+        assert_eq!(spec.bg(), None);
+        assert!(spec.bold());
+    }
+
+    #[test]
+    fn test_try_set_color() {
+        use std::io::Write;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+        let mut blue = ColorSpec::new();
+        blue.set_fg(Some(Color::Blue));
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        assert!(buf.try_set_color(&red).unwrap());
+        let len = buf.get_ref().len();
+        assert!(!buf.try_set_color(&red).unwrap());
+        assert_eq!(buf.get_ref().len(), len);
+        buf.write_all(b"a").unwrap();
+        assert!(!buf.try_set_color(&red).unwrap());
+        assert!(buf.try_set_color(&blue).unwrap());
+        buf.set_color(&red).unwrap();
+        assert!(!buf.try_set_color(&red).unwrap());
+        buf.reset().unwrap();
+        assert!(buf.try_set_color(&red).unwrap());
+        assert_eq!(
+            buf.into_inner(),
+            b"\x1B[0m\x1B[31ma\x1B[0m\x1B[34m\x1B[0m\x1B[31m\x1B[0m\x1B[0m\x1B[31m"
+                .to_vec()
+        );
+
+        // Restoring the cursor may restore other colors, so the colors are
+        // applied again.
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        buf.save_cursor().unwrap();
+        assert!(buf.try_set_color(&red).unwrap());
+        buf.restore_cursor().unwrap();
+        assert!(buf.try_set_color(&red).unwrap());
+
+        // Clearing a buffer forgets the color settings along with the data.
+        let mut buf = Buffer::ansi();
+        assert!(buf.try_set_color(&red).unwrap());
+        buf.clear();
+        assert!(buf.try_set_color(&red).unwrap());
+        assert_eq!(buf.as_slice(), b"\x1B[0m\x1B[31m");
+
+        let mut buf = Buffer::no_color();
+        assert!(!buf.try_set_color(&red).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_sink() {
+        use std::io::Write;
+
+        let mut wtr = Sink::new();
+        assert!(!wtr.supports_color());
+        assert!(!wtr.supports_hyperlinks());
+        wtr.set_color(ColorSpec::new().set_fg(Some(Color::Red))).unwrap();
+        assert!(!wtr.try_set_color(&ColorSpec::new()).unwrap());
+        wtr.set_hyperlink(&HyperlinkSpec::open(b"https://example.com"))
+            .unwrap();
+        write!(wtr, "foo").unwrap();
+        assert_eq!(wtr.write(b"bar").unwrap(), 3);
+        wtr.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        wtr.reset().unwrap();
+        wtr.flush().unwrap();
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut buf = Ansi::new(vec![]);
+        buf.alternate_screen_enter().unwrap();
+        buf.alternate_screen_exit().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[?1049h\x1B[?1049l");
+    }
+
+    #[test]
+    fn test_cursor_visibility() {
+        let mut buf = Ansi::new(vec![]);
+        buf.set_cursor_visibility(false).unwrap();
+        buf.set_cursor_visibility(true).unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[?25l\x1B[?25h");
+    }
+
+    #[test]
+    fn test_synchronized_output() {
+        let mut buf = Ansi::new(vec![]);
+        buf.begin_synchronized_output().unwrap();
+        buf.end_synchronized_output().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[?2026h\x1B[?2026l");
+    }
+
+    #[test]
+    fn test_ansi_guards() {
+        use std::io::Write;
+
+        // Each guard undoes what it did when it's dropped, even when a panic
+        // unwinds past it, and flushes the writer afterwards.
+        macro_rules! check {
+            ($guard:ident, $enter:expr, $exit:expr) => {{
+                let mut buf = Ansi::new(io::BufWriter::new(vec![]));
+                let result = std::panic::catch_unwind(
+                    std::panic::AssertUnwindSafe(|| {
+                        let mut guard = $guard::new(&mut buf).unwrap();
+                        guard.write_all(b"foo").unwrap();
+                        panic!("oops");
+                    }),
+                );
+                assert!(result.is_err());
+                assert!(buf.get_ref().buffer().is_empty());
+                let expected = [&$enter[..], b"foo", &$exit[..]].concat();
+                assert_eq!(buf.get_ref().get_ref(), &expected);
+            }};
+        }
+
+        check!(SavedCursor, b"\x1B7", b"\x1B8");
+        check!(AlternateScreen, b"\x1B[?1049h", b"\x1B[?1049l");
+        check!(HiddenCursor, b"\x1B[?25l", b"\x1B[?25h");
+        check!(SynchronizedUpdate, b"\x1B[?2026h", b"\x1B[?2026l");
+    }
+
+    #[test]
+    fn test_underline_style() {
+        let styles = [
+            (UnderlineStyle::Straight, "\x1B[0m\x1B[4m"),
+            (UnderlineStyle::Double, "\x1B[0m\x1B[4:2m"),
+            (UnderlineStyle::Curly, "\x1B[0m\x1B[4:3m"),
+            (UnderlineStyle::Dotted, "\x1B[0m\x1B[4:4m"),
+            (UnderlineStyle::Dashed, "\x1B[0m\x1B[4:5m"),
+        ];
+        for &(style, expected) in &styles {
+            let mut spec = ColorSpec::new();
+            spec.set_underline(true).set_underline_style(style);
+            assert_eq!(spec.underline_style(), style);
+            let mut buf = Ansi::new(vec![]);
+            buf.set_color(&spec).unwrap();
+            assert_eq!(buf.into_inner(), expected.as_bytes());
+        }
+
+        // The style alone doesn't underline anything.
+        let mut spec = ColorSpec::new();
+        spec.set_underline_style(UnderlineStyle::Curly);
+        let mut buf = Ansi::new(vec![]);
+        buf.set_color(&spec).unwrap();
+        assert_eq!(buf.into_inner(), b"\x1B[0m");
+
+        spec.clear();
+        assert_eq!(spec.underline_style(), UnderlineStyle::Straight);
+    }
+
+    #[test]
+    fn test_html() {
+        use std::io::Write;
+
+        let mut wtr = Html::new(vec![]);
+        write!(wtr, "<a href='x'>&\"</a>").unwrap();
+        assert_eq!(
+            wtr.get_ref(),
+            b"&lt;a href=&#39;x&#39;&gt;&amp;&quot;&lt;/a&gt;"
+        );
+
+        let mut wtr = Html::new(vec![]);
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Green))
+            .set_bg(Some(Color::Rgb(0x12, 0xab, 0xff)))
+            .set_underline(true);
+        wtr.set_color(&spec).unwrap();
+        write!(wtr, "a").unwrap();
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Ansi256(196))).set_intense(true);
+        wtr.set_color(&spec).unwrap();
+        write!(wtr, "b").unwrap();
+        let mut spec = ColorSpec::new();
+        spec.set_reset(false)
+            .set_bold(true)
+            .set_underline(true)
+            .set_strikethrough(true);
+        wtr.set_color(&spec).unwrap();
+        write!(wtr, "c").unwrap();
+        wtr.set_color(
+            ColorSpec::new().set_fg(Some(Color::Blue)).set_intense(true),
+        )
+        .unwrap();
+        write!(wtr, "d").unwrap();
+        wtr.reset().unwrap();
+        wtr.set_color(&ColorSpec::new()).unwrap();
+        write!(wtr, "e").unwrap();
+        wtr.set_color(
+            ColorSpec::new()
+                .set_underline(true)
+                .set_underline_style(UnderlineStyle::Curly),
+        )
+        .unwrap();
+        write!(wtr, "f").unwrap();
+        wtr.set_color(
+            ColorSpec::new()
+                .set_underline(true)
+                .set_underline_style(UnderlineStyle::Double)
+                .set_strikethrough(true),
+        )
+        .unwrap();
+        write!(wtr, "g").unwrap();
+        // The style has no effect without an underline.
+        wtr.set_color(
+            ColorSpec::new()
+                .set_italic(true)
+                .set_underline_style(UnderlineStyle::Dashed),
+        )
+        .unwrap();
+        write!(wtr, "h").unwrap();
+        wtr.reset().unwrap();
+        assert_eq!(
+            String::from_utf8(wtr.into_inner()).unwrap(),
+            "<span style=\"color:#00cd00;background-color:#12abff;\
+             text-decoration:underline\">a</span>\
+             <span style=\"color:#ff0000\">b\
+             <span style=\"font-weight:bold;\
+             text-decoration:underline line-through\">c</span></span>\
+             <span style=\"color:#5c5cff\">d</span>\
+             e\
+             <span style=\"text-decoration:underline;\
+             text-decoration-style:wavy\">f</span>\
+             <span style=\"text-decoration:underline line-through;\
+             text-decoration-style:double\">g</span>\
+             <span style=\"font-style:italic\">h</span>"
+        );
+    }
+
+    #[test]
+    fn test_color_hsl() {
+        assert_eq!(Color::from_hsl(0.0, 0.0, 0.0), Color::Rgb(0, 0, 0));
+        assert_eq!(Color::from_hsl(0.0, 0.0, 1.0), Color::Rgb(255, 255, 255));
+        assert_eq!(Color::from_hsl(120.0, 1.0, 0.5), Color::Rgb(0, 255, 0));
+        assert_eq!(Color::from_hsl(240.0, 1.0, 0.5), Color::Rgb(0, 0, 255));
+        assert_eq!(Color::from_hsl(60.0, 1.0, 0.25), Color::Rgb(128, 128, 0));
+        assert_eq!(Color::from_hsl(300.0, 0.5, 0.5), Color::Rgb(191, 64, 191));
+        // Out of range values are clamped.
+        assert_eq!(Color::from_hsl(-10.0, 2.0, 0.5), Color::Rgb(255, 0, 0));
+        assert_eq!(Color::from_hsl(400.0, 1.0, 0.5), Color::Rgb(255, 0, 0));
+        assert_eq!(Color::from_hsl(0.0, 1.0, 7.0), Color::Rgb(255, 255, 255));
+
+        assert_eq!(Color::Rgb(0, 0, 0).to_hsl(), Some((0.0, 0.0, 0.0)));
+        assert_eq!(Color::Rgb(0, 0, 255).to_hsl(), Some((240.0, 1.0, 0.5)));
+        assert_eq!(Color::Ansi256(196).to_hsl(), Some((0.0, 1.0, 0.5)));
+        assert_eq!(Color::Ansi256(9).to_hsl(), None);
+        assert_eq!(Color::Blue.to_hsl(), None);
+        assert_eq!(Color::Default.to_hsl(), None);
+
+        for &(r, g, b) in &[(12, 200, 99), (250, 128, 7), (90, 90, 91)] {
+            let (h, s, l) = Color::Rgb(r, g, b).to_hsl().unwrap();
+            assert_eq!(Color::from_hsl(h, s, l), Color::Rgb(r, g, b));
         }
     }
-}
 
-/// An error from parsing an invalid color specification.
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct ParseColorError {
-    kind: ParseColorErrorKind,
-    given: String,
-}
+    #[test]
+    fn test_buffer_append() {
+        use std::io::Write;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+
+        let mut buf1 = Buffer::ansi();
+        buf1.set_color(&red).unwrap();
+        buf1.write_all(b"foo").unwrap();
+        let mut buf2 = Buffer::ansi();
+        buf2.write_all(b"bar").unwrap();
+        buf2.reset().unwrap();
+        buf1.append(&buf2);
+        assert_eq!(buf1.as_slice(), b"\x1B[0m\x1B[31mfoobar\x1B[0m");
+        // The buffer no longer knows which colors are set, so setting red
+        // again isn't skipped.
+        assert!(buf1.try_set_color(&red).unwrap());
+
+        let mut plain = Buffer::no_color();
+        plain.write_all(b"<").unwrap();
+        plain.append(&buf2);
+        plain.append(&Buffer::no_color());
+        assert_eq!(plain.as_slice(), b"<bar");
+
+        let mut buf3 = Buffer::ansi();
+        buf3.write_all(b">").unwrap();
+        buf3.append(&plain);
+        assert_eq!(buf3.as_slice(), b"><bar");
+    }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-enum ParseColorErrorKind {
-    InvalidName,
-    InvalidAnsi256,
-    InvalidRgb,
-}
+    #[cfg(windows)]
+    #[test]
+    fn test_buffer_append_console() {
+        use std::io::Write;
+
+        use super::BufferInner;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+
+        let mut buf1 = Buffer::console();
+        buf1.write_all(b"ab").unwrap();
+        buf1.set_color(&red).unwrap();
+        buf1.write_all(b"c").unwrap();
+        let mut buf2 = Buffer::console();
+        buf2.set_color(&red).unwrap();
+        buf2.write_all(b"de").unwrap();
+        buf2.reset().unwrap();
+        buf1.append(&buf2);
+        assert_eq!(buf1.as_slice(), b"abcde");
+        match buf1.0 {
+            BufferInner::Windows(ref b) => assert_eq!(
+                b.colors,
+                vec![
+                    (2, Some(red.clone())),
+                    (3, Some(red.clone())),
+                    (5, None)
+                ]
+            ),
+            _ => unreachable!(),
+        }
 
-impl ParseColorError {
-    /// Return the string that couldn't be parsed as a valid color.
-    pub fn invalid(&self) -> &str {
-        &self.given
+        let mut plain = Buffer::no_color();
+        plain.append(&buf1);
+        assert_eq!(plain.as_slice(), b"abcde");
     }
-}
 
-impl error::Error for ParseColorError {
-    fn description(&self) -> &str {
-        use self::ParseColorErrorKind::*;
-        match self.kind {
-            InvalidName => "unrecognized color name",
-            InvalidAnsi256 => "invalid ansi256 color number",
-            InvalidRgb => "invalid RGB color triple",
+    #[test]
+    fn test_color_hash() {
+        use std::collections::HashMap;
+
+        let mut counts = HashMap::new();
+        let colors = [
+            Color::Red,
+            Color::Ansi256(1),
+            Color::Rgb(1, 2, 3),
+            Color::Red,
+            Color::Rgb(1, 2, 3),
+            Color::Rgb(3, 2, 1),
+            Color::Ansi256(1),
+            Color::Default,
+        ];
+        for &c in &colors {
+            *counts.entry(c).or_insert(0) += 1;
         }
+        assert_eq!(counts.len(), 5);
+        assert_eq!(counts[&Color::Red], 2);
+        assert_eq!(counts[&Color::Ansi256(1)], 2);
+        assert_eq!(counts[&Color::Rgb(1, 2, 3)], 2);
+        assert_eq!(counts[&Color::Rgb(3, 2, 1)], 1);
+        assert_eq!(counts[&Color::Default], 1);
     }
-}
 
-impl fmt::Display for ParseColorError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use self::ParseColorErrorKind::*;
-        match self.kind {
-            InvalidName => write!(
-                f,
-                "unrecognized color name '{}'. Choose from: \
-                 black, blue, green, red, cyan, magenta, yellow, \
-                 white",
-                self.given
-            ),
-            InvalidAnsi256 => write!(
-                f,
-                "unrecognized ansi256 color number, \
-                 should be '[0-255]' (or a hex number), but is '{}'",
-                self.given
-            ),
-            InvalidRgb => write!(
-                f,
-                "unrecognized RGB color triple, \
-                 should be '[0-255],[0-255],[0-255]' (or a hex \
-                 triple), but is '{}'",
-                self.given
-            ),
-        }
+    #[test]
+    fn test_no_color_strict() {
+        use std::io::Write;
+
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red));
+
+        let mut wtr = NoColor::new(vec![]);
+        wtr.set_color(&spec).unwrap();
+        wtr.write_all(b"foo").unwrap();
+        wtr.reset().unwrap();
+        assert_eq!(wtr.get_ref(), b"foo");
+
+        wtr.strict(true);
+        let err = wtr.set_color(&spec).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let err = wtr.reset().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        wtr.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        wtr.write_all(b"bar").unwrap();
+        assert_eq!(wtr.get_ref(), b"foobar");
+
+        wtr.strict(false);
+        wtr.set_color(&spec).unwrap();
+        wtr.reset().unwrap();
     }
-}
 
-impl FromStr for Color {
-    type Err = ParseColorError;
+    #[test]
+    fn test_attrs() {
+        let all = [
+            Attributes::BOLD,
+            Attributes::DIMMED,
+            Attributes::ITALIC,
+            Attributes::UNDERLINE,
+            Attributes::STRIKETHROUGH,
+            Attributes::INTENSE,
+        ];
 
-    fn from_str(s: &str) -> Result<Color, ParseColorError> {
-        match &*s.to_lowercase() {
-            "black" => Ok(Color::Black),
-            "blue" => Ok(Color::Blue),
-            "green" => Ok(Color::Green),
-            "red" => Ok(Color::Red),
-            "cyan" => Ok(Color::Cyan),
-            "magenta" => Ok(Color::Magenta),
-            "yellow" => Ok(Color::Yellow),
-            "white" => Ok(Color::White),
-            _ => Color::from_str_numeric(s),
+        let mut spec = ColorSpec::new();
+        assert!(spec.attrs().is_empty());
+        spec.set_bold(true)
+            .set_dimmed(true)
+            .set_italic(true)
+            .set_underline(true)
+            .set_strikethrough(true)
+            .set_intense(true);
+        let attrs = spec.attrs();
+        for &attr in &all {
+            assert!(attrs.contains(attr));
         }
-    }
-}
 
-/// A hyperlink specification.
-#[derive(Clone, Debug)]
-pub struct HyperlinkSpec<'a> {
-    uri: Option<&'a [u8]>,
-}
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red)).set_bold(true).set_reset(false);
+        spec.set_attrs(Attributes::ITALIC | Attributes::STRIKETHROUGH);
+        assert!(!spec.bold());
+        assert!(spec.italic());
+        assert!(spec.strikethrough());
+        assert!(!spec.underline());
+        assert_eq!(spec.fg(), Some(&Color::Red));
+        assert!(!spec.reset());
+        assert_eq!(
+            spec.attrs(),
+            Attributes::ITALIC | Attributes::STRIKETHROUGH
+        );
 
-impl<'a> HyperlinkSpec<'a> {
-    /// Creates a new hyperlink specification.
-    pub fn open(uri: &'a [u8]) -> HyperlinkSpec<'a> {
-        HyperlinkSpec { uri: Some(uri) }
-    }
+        for &attr in &all {
+            let mut spec = ColorSpec::new();
+            spec.set_attrs(attr);
+            assert_eq!(spec.attrs(), attr);
+            assert_eq!(spec.active_attributes().len(), 1);
+        }
 
-    /// Creates a hyperlink specification representing no hyperlink.
-    pub fn close() -> HyperlinkSpec<'a> {
-        HyperlinkSpec { uri: None }
+        spec.set_attrs(Attributes::NONE);
+        assert!(spec.attrs().is_empty());
+        assert!(!spec.italic());
     }
 
-    /// Returns the URI of the hyperlink if one is attached to this spec.
-    pub fn uri(&self) -> Option<&'a [u8]> {
-        self.uri
+    #[test]
+    fn test_color_spec_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        use std::hash::{Hash, Hasher};
+
+        fn hash<T: Hash>(t: &T) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            t.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let mut spec1 = ColorSpec::new();
+        spec1.set_fg(Some(Color::Rgb(1, 2, 3))).set_bold(true);
+        let mut spec2 = ColorSpec::new();
+        spec2.set_bold(true).set_fg(Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(spec1, spec2);
+        assert_eq!(hash(&spec1), hash(&spec2));
+        assert_eq!(hash(&Color::Ansi256(7)), hash(&Color::Ansi256(7)));
+
+        let mut set = HashSet::new();
+        set.insert(spec1.clone());
+        set.insert(spec2.clone());
+        set.insert(ColorSpec::new());
+        spec2.set_underline(true);
+        set.insert(spec2.clone());
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&spec1));
+        assert!(set.contains(&spec2));
     }
-}
 
-#[derive(Debug)]
-struct LossyStandardStream<W> {
-    wtr: W,
-    #[cfg(windows)]
-    is_console: bool,
-}
+    #[test]
+    fn test_conditional_color() {
+        use std::io::Write;
 
-impl<W: io::Write> LossyStandardStream<W> {
-    #[cfg(not(windows))]
-    fn new(wtr: W) -> LossyStandardStream<W> {
-        LossyStandardStream { wtr }
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red));
+        let mut buf = Ansi::new(vec![]);
+        for &enabled in &[false, true] {
+            buf.conditional_color(enabled, &spec).unwrap();
+            buf.write_all(b"x").unwrap();
+            buf.conditional_reset(enabled).unwrap();
+        }
+        assert_eq!(buf.into_inner(), b"x\x1B[0m\x1B[31mx\x1B[0m".to_vec());
     }
 
-    #[cfg(windows)]
-    fn new(wtr: W) -> LossyStandardStream<W> {
-        let is_console = wincon::Console::stdout().is_ok()
-            || wincon::Console::stderr().is_ok();
-        LossyStandardStream { wtr, is_console }
-    }
+    #[test]
+    fn test_set_hyperlink_with() {
+        let mut buf = Ansi::new(vec![]);
+        buf.set_hyperlink_with(&[("id", "foo")], b"https://example.com")
+            .unwrap();
+        buf.set_hyperlink_with(&[("id", "a-1"), ("x", "")], b"file:///tmp")
+            .unwrap();
+        buf.set_hyperlink_with(&[], b"https://example.com").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B]8;id=foo;https://example.com\x1B\\\
+              \x1B]8;id=a-1:x=;file:///tmp\x1B\\\
+              \x1B]8;;https://example.com\x1B\\\
+              \x1B]8;;\x1B\\"
+        );
 
-    #[cfg(not(windows))]
-    fn wrap<Q: io::Write>(&self, wtr: Q) -> LossyStandardStream<Q> {
-        LossyStandardStream::new(wtr)
+        let invalid = [
+            ("", "foo"),
+            ("id", "a:b"),
+            ("id", "a;b"),
+            ("i=d", "foo"),
+            ("id", "foo\x1B"),
+            ("i\nd", "foo"),
+        ];
+        for &param in &invalid {
+            let mut buf = Ansi::new(vec![]);
+            let err = buf
+                .set_hyperlink_with(
+                    &[("id", "ok"), param],
+                    b"https://example.com",
+                )
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(buf.get_ref().is_empty());
+        }
+
+        let mut buf = NoColor::new(vec![]);
+        buf.set_hyperlink_with(&[("id", "foo")], b"https://example.com")
+            .unwrap();
+        assert!(buf.get_ref().is_empty());
+
+        let mut buf = Buffer::ansi();
+        buf.set_hyperlink_with(&[("id", "foo")], b"x").unwrap();
+        assert_eq!(buf.as_slice(), b"\x1B]8;id=foo;x\x1B\\");
     }
 
-    #[cfg(windows)]
-    fn wrap<Q: io::Write>(&self, wtr: Q) -> LossyStandardStream<Q> {
-        LossyStandardStream { wtr, is_console: self.is_console }
+    #[test]
+    fn test_move_cursor_to() {
+        let mut buf = Ansi::new(vec![]);
+        buf.move_cursor_to(1, 1).unwrap();
+        buf.move_cursor_to(24, 80).unwrap();
+        buf.move_cursor_to(u16::MAX, 3).unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[1;1H\x1B[24;80H\x1B[65535;3H");
+
+        let mut buf = Ansi::new(vec![]);
+        for &(row, col) in &[(0, 1), (1, 0), (0, 0)] {
+            let err = buf.move_cursor_to(row, col).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(buf.get_ref().is_empty());
     }
 
-    fn get_ref(&self) -> &W {
-        &self.wtr
+    #[test]
+    fn test_cursor_style() {
+        let styles = [
+            CursorStyle::DefaultUserShape,
+            CursorStyle::BlinkingBlock,
+            CursorStyle::SteadyBlock,
+            CursorStyle::BlinkingUnderline,
+            CursorStyle::SteadyUnderline,
+            CursorStyle::BlinkingBar,
+            CursorStyle::SteadyBar,
+        ];
+        for (n, &style) in styles.iter().enumerate() {
+            let mut buf = Ansi::new(vec![]);
+            buf.set_cursor_style(style).unwrap();
+            assert_eq!(buf.get_ref(), format!("\x1B[{} q", n).as_bytes());
+        }
+
+        let mut buf = Ansi::new(vec![]);
+        buf.reset_cursor_style().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[0 q");
     }
-}
 
-impl<W: WriteColor> WriteColor for LossyStandardStream<W> {
-    fn supports_color(&self) -> bool {
-        self.wtr.supports_color()
+    #[test]
+    fn test_color_u32() {
+        assert_eq!(Color::from_u32(0), Color::Rgb(0, 0, 0));
+        assert_eq!(Color::from_u32(0x00FFFFFF), Color::Rgb(255, 255, 255));
+        assert_eq!(Color::from_u32(0xAB010203), Color::Rgb(1, 2, 3));
+
+        for &rgb in &[0, 0x00123456, 0x00FFFFFF, 0x00FF8700] {
+            assert_eq!(Color::from_u32(rgb).to_u32(), Some(rgb));
+        }
+        assert_eq!(Color::Ansi256(16).to_u32(), Some(0));
+        assert_eq!(Color::Ansi256(231).to_u32(), Some(0x00FFFFFF));
+        assert_eq!(Color::Ansi256(15).to_u32(), None);
+        assert_eq!(Color::Blue.to_u32(), None);
+        assert_eq!(Color::Default.to_u32(), None);
     }
-    fn supports_hyperlinks(&self) -> bool {
-        self.wtr.supports_hyperlinks()
+
+    #[test]
+    fn test_sanitize_hyperlink_uri() {
+        let uri = b"https://example.com/a?b=c;d#e";
+        assert!(matches!(sanitize_hyperlink_uri(uri), Cow::Borrowed(_)));
+        assert_eq!(&*sanitize_hyperlink_uri(uri), uri);
+
+        assert_eq!(
+            &*sanitize_hyperlink_uri(b"a\x1B\\b\x07c\nd\x00e\x7Ff\x1Fg"),
+            b"a%1B\\b%07c%0Ad%00e%7Ff%1Fg",
+        );
+        assert_eq!(
+            &*sanitize_hyperlink_uri("file:///tmp/\u{e9}".as_bytes()),
+            "file:///tmp/\u{e9}".as_bytes(),
+        );
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_hyperlink(&HyperlinkSpec::open(b"x\x1B]8;;evil\x07")).unwrap();
+        buf.set_hyperlink_with(&[("id", "1")], b"y\r\n").unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B]8;;x%1B]8;;evil%07\x1B\\\x1B]8;id=1;y%0D%0A\x1B\\"
+        );
     }
-    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
-        self.wtr.set_color(spec)
+
+    #[test]
+    fn test_set_color_skips_identical_spec() {
+        use std::io::Write;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+        let mut bold = red.clone();
+        bold.set_bold(true);
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        buf.set_color(&red).unwrap();
+        let len = buf.get_ref().len();
+        for _ in 0..10 {
+            buf.set_color(&red).unwrap();
+            assert_eq!(buf.get_ref().len(), len);
+        }
+        buf.write_all(b"a").unwrap();
+        buf.set_color(&red).unwrap();
+        assert_eq!(buf.get_ref().len(), len + 1);
+
+        // Any change to the spec is written in full.
+        buf.set_color(&bold).unwrap();
+        buf.set_color(&bold).unwrap();
+        buf.set_color(&red).unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B[0m\x1B[31ma\x1B[0m\x1B[1m\x1B[31m\x1B[0m\x1B[31m"
+        );
+
+        // Resetting forgets the last spec.
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        buf.set_color(&red).unwrap();
+        buf.reset().unwrap();
+        buf.set_color(&red).unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[0m\x1B[31m\x1B[0m\x1B[0m\x1B[31m");
+
+        // So does changing how colors are written.
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        let mut orange = ColorSpec::new();
+        orange.set_fg(Some(Color::Rgb(255, 135, 0)));
+        buf.set_color(&orange).unwrap();
+        buf.set_color_level(ColorLevel::Basic);
+        buf.set_color(&orange).unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B[0m\x1B[38;2;255;135;0m\x1B[0m\x1B[33m"
+        );
+
+        // Nothing is skipped unless enabled.
+        let mut buf = Ansi::new(vec![]);
+        buf.set_color(&red).unwrap();
+        buf.set_color(&red).unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[0m\x1B[31m\x1B[0m\x1B[31m");
+
+        // Restoring the cursor and switching screens also restore or change
+        // the colors in effect, so they forget the last spec too.
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        buf.save_cursor().unwrap();
+        buf.set_color(&red).unwrap();
+        buf.restore_cursor().unwrap();
+        buf.set_color(&red).unwrap();
+        write!(buf, "x").unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B7\x1B[0m\x1B[31m\x1B8\x1B[0m\x1B[31mx"
+        );
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_dedup_colors(true);
+        buf.set_color(&red).unwrap();
+        buf.alternate_screen_enter().unwrap();
+        buf.set_color(&red).unwrap();
+        buf.alternate_screen_exit().unwrap();
+        buf.set_color(&red).unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B[0m\x1B[31m\x1B[?1049h\x1B[0m\x1B[31m\
+              \x1B[?1049l\x1B[0m\x1B[31m"
+        );
     }
-    fn set_hyperlink(&mut self, link: &HyperlinkSpec) -> io::Result<()> {
-        self.wtr.set_hyperlink(link)
+
+    #[cfg(unix)]
+    #[test]
+    fn test_standard_stream_lock_reset_forgets_color() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+
+        let path = temp_path("lock-reset");
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+        let mut stream =
+            unsafe { StandardStream::from_raw_fd(fd, ColorChoice::Always) };
+        assert!(stream.try_set_color(&red).unwrap());
+        write!(stream, "a").unwrap();
+        {
+            let mut lock = stream.lock();
+            lock.reset().unwrap();
+            write!(lock, "b").unwrap();
+        }
+        assert!(stream.try_set_color(&red).unwrap());
+        write!(stream, "c").unwrap();
+        drop(stream);
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, b"\x1B[0m\x1B[31ma\x1B[0mb\x1B[0m\x1B[31mc");
     }
-    fn reset(&mut self) -> io::Result<()> {
-        self.wtr.reset()
+
+    #[test]
+    fn test_color_spec_merge() {
+        let mut base = ColorSpec::new();
+        base.set_fg(Some(Color::Red))
+            .set_bg(Some(Color::Black))
+            .set_italic(true)
+            .set_underline(true)
+            .set_underline_style(UnderlineStyle::Curly);
+        let mut layer = ColorSpec::new();
+        layer.set_bg(Some(Color::Blue)).set_bold(true).set_reset(false);
+
+        let mut merged = base.clone();
+        merged.merge(&layer);
+        let mut expected = ColorSpec::new();
+        expected
+            .set_fg(Some(Color::Red))
+            .set_bg(Some(Color::Blue))
+            .set_bold(true)
+            .set_italic(true)
+            .set_underline(true)
+            .set_underline_style(UnderlineStyle::Curly)
+            .set_reset(false);
+        assert_eq!(merged, expected);
+
+        assert_eq!(base.clone() | layer.clone(), merged);
+        assert_eq!(base.clone() | &layer, merged);
+        let mut assigned = base.clone();
+        assigned |= &layer;
+        assert_eq!(assigned, merged);
+
+        // Merging a default spec changes nothing, in either direction.
+        assert_eq!(base.clone() | ColorSpec::new(), base);
+        assert_eq!(ColorSpec::new() | &base, base);
+
+        // The underline style is taken from the right hand side only when it
+        // is underlined.
+        let mut double = ColorSpec::new();
+        double.set_underline(true).set_underline_style(UnderlineStyle::Double);
+        assert_eq!(
+            (base.clone() | &double).underline_style(),
+            UnderlineStyle::Double
+        );
     }
-    fn is_synchronous(&self) -> bool {
-        self.wtr.is_synchronous()
+
+    #[test]
+    fn test_define_xterm_color() {
+        let mut buf = Ansi::new(vec![]);
+        buf.define_xterm_color(1, 255, 0, 10).unwrap();
+        buf.define_xterm_color(255, 0xAB, 0xCD, 0xEF).unwrap();
+        buf.reset_xterm_color(1).unwrap();
+        buf.reset_xterm_color(255).unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            b"\x1B]4;1;rgb:ff/00/0a\x1B\\\
+              \x1B]4;255;rgb:ab/cd/ef\x1B\\\
+              \x1B]104;1\x1B\\\
+              \x1B]104;255\x1B\\"
+        );
     }
-}
 
-impl<W: io::Write> io::Write for LossyStandardStream<W> {
-    #[cfg(not(windows))]
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.wtr.write(buf)
+    #[test]
+    fn test_standard_stream_new() {
+        let kinds = [
+            (StandardStreamKind::Stdout, true),
+            (StandardStreamKind::Stderr, false),
+        ];
+        for &(kind, is_stdout) in &kinds {
+            let stream = StandardStream::new(kind, ColorChoice::Never);
+            assert!(!stream.supports_color());
+            match stream.wtr.wtr {
+                WriterInner::NoColor(ref w) => match w.wtr {
+                    IoStandardStream::Stdout(_) => assert!(is_stdout),
+                    IoStandardStream::Stderr(_) => assert!(!is_stdout),
+                    _ => panic!("unexpected stream for {:?}", kind),
+                },
+                _ => panic!("expected a writer without colors"),
+            }
+        }
     }
 
-    #[cfg(windows)]
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if self.is_console {
-            write_lossy_utf8(&mut self.wtr, buf)
-        } else {
-            self.wtr.write(buf)
+    #[test]
+    fn test_buffer_apply_to() {
+        use std::io::Write;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+        let mut fancy = ColorSpec::new();
+        fancy
+            .set_fg(Some(Color::Rgb(1, 2, 3)))
+            .set_bg(Some(Color::Ansi256(200)))
+            .set_bold(true)
+            .set_italic(true)
+            .set_underline(true)
+            .set_underline_style(UnderlineStyle::Curly)
+            .set_strikethrough(true);
+        let mut intense = ColorSpec::new();
+        intense
+            .set_fg(Some(Color::Green))
+            .set_bg(Some(Color::Default))
+            .set_dimmed(true)
+            .set_intense(true);
+
+        let mut buf = Buffer::ansi();
+        write!(buf, "a").unwrap();
+        buf.set_color(&red).unwrap();
+        write!(buf, "b").unwrap();
+        buf.set_color(&fancy).unwrap();
+        buf.set_hyperlink_with(
+            &[("id", "1"), ("foo", "bar")],
+            b"https://example.com",
+        )
+        .unwrap();
+        write!(buf, "c").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        buf.set_color(&intense).unwrap();
+        write!(buf, "d\x1B[2Ke").unwrap();
+        buf.reset().unwrap();
+        write!(buf, "f").unwrap();
+
+        // Applying to the same kind of buffer reproduces it, including the
+        // hyperlink parameters.
+        let mut replayed = Buffer::ansi();
+        buf.apply_to(&mut replayed).unwrap();
+        assert_eq!(
+            String::from_utf8(replayed.as_slice().to_vec()).unwrap(),
+            String::from_utf8(buf.as_slice().to_vec()).unwrap(),
+        );
+
+        let mut no_color = Buffer::no_color();
+        buf.apply_to(&mut no_color).unwrap();
+        assert_eq!(no_color.as_slice(), b"abcd\x1B[2Kef");
+
+        let mut events = vec![];
+        for event in AnsiEvents::new(buf.as_slice()) {
+            events.push(event);
         }
+        assert_eq!(
+            events,
+            vec![
+                AnsiEvent::Text(b"a"),
+                AnsiEvent::Color(Some(red.clone())),
+                AnsiEvent::Text(b"b"),
+                AnsiEvent::Color(Some(fancy.clone())),
+                AnsiEvent::Hyperlink(
+                    b"id=1:foo=bar",
+                    Some(b"https://example.com")
+                ),
+                AnsiEvent::Text(b"c"),
+                AnsiEvent::Hyperlink(b"", None),
+                AnsiEvent::Color(Some(
+                    ColorSpec::new()
+                        .set_fg(Some(Color::Ansi256(10)))
+                        .set_bg(Some(Color::Default))
+                        .set_dimmed(true)
+                        .clone()
+                )),
+                AnsiEvent::Text(b"d"),
+                AnsiEvent::Escape(b"\x1B[2K"),
+                AnsiEvent::Text(b"e"),
+                AnsiEvent::Color(None),
+                AnsiEvent::Text(b"f"),
+            ]
+        );
+
+        let mut buf = Buffer::no_color();
+        buf.set_color(&red).unwrap();
+        write!(buf, "plain").unwrap();
+        let mut replayed = Ansi::new(vec![]);
+        buf.apply_to(&mut replayed).unwrap();
+        assert_eq!(replayed.get_ref(), b"plain");
+
+        assert_eq!(AnsiEvents::hyperlink_params(b""), Some(vec![]));
+        assert_eq!(
+            AnsiEvents::hyperlink_params(b"id=1:foo=bar"),
+            Some(vec![("id", "1"), ("foo", "bar")])
+        );
+        assert_eq!(AnsiEvents::hyperlink_params(b"id"), None);
+        assert_eq!(AnsiEvents::hyperlink_params(b"id=\xFF"), None);
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.wtr.flush()
+    #[test]
+    fn test_apply_sgr() {
+        let sgr = |params: &str| {
+            let mut spec = ColorSpec::new();
+            apply_sgr(&mut spec, params.as_bytes());
+            spec
+        };
+        assert_eq!(sgr(""), ColorSpec::new());
+        assert_eq!(sgr("1;31;42"), {
+            let mut spec = ColorSpec::new();
+            spec.set_bold(true)
+                .set_fg(Some(Color::Red))
+                .set_bg(Some(Color::Green));
+            spec
+        });
+        assert_eq!(sgr("38;5;100;48;2;1;2;3"), {
+            let mut spec = ColorSpec::new();
+            spec.set_fg(Some(Color::Ansi256(100)))
+                .set_bg(Some(Color::Rgb(1, 2, 3)));
+            spec
+        });
+        assert_eq!(sgr("91;107"), {
+            let mut spec = ColorSpec::new();
+            spec.set_fg(Some(Color::Ansi256(9)))
+                .set_bg(Some(Color::Ansi256(15)));
+            spec
+        });
+        assert_eq!(sgr("4:5").underline_style(), UnderlineStyle::Dashed);
+        assert_eq!(sgr("1;2;3;4;9;22;23;24;29"), ColorSpec::new());
+        assert_eq!(sgr("1;31;0"), ColorSpec::new());
+        // Malformed and unrecognized parameters are ignored.
+        assert_eq!(sgr("x;38;5;999;5;1000;1"), {
+            let mut spec = ColorSpec::new();
+            spec.set_bold(true);
+            spec
+        });
     }
-}
 
-#[cfg(windows)]
-fn write_lossy_utf8<W: io::Write>(mut w: W, buf: &[u8]) -> io::Result<usize> {
-    match ::std::str::from_utf8(buf) {
-        Ok(s) => w.write(s.as_bytes()),
-        Err(ref e) if e.valid_up_to() == 0 => {
-            w.write(b"\xEF\xBF\xBD")?;
-            Ok(1)
-        }
-        Err(e) => w.write(&buf[..e.valid_up_to()]),
+    #[test]
+    fn test_buffer_color_spans_ansi() {
+        use std::io::Write;
+
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+        let mut bold = ColorSpec::new();
+        bold.set_bold(true).set_bg(Some(Color::Rgb(0, 0, 255)));
+
+        let mut buf = Buffer::ansi();
+        buf.set_color(&red).unwrap();
+        write!(buf, "ab").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::open(b"https://example.com"))
+            .unwrap();
+        buf.set_color(&bold).unwrap();
+        write!(buf, "c\x1B[2Kd").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        buf.reset().unwrap();
+        write!(buf, "ef").unwrap();
+        buf.set_color(&red).unwrap();
+        assert_eq!(
+            buf.color_spans(),
+            vec![
+                (0, Some(red.clone())),
+                (2, Some(bold.clone())),
+                (4, None),
+                (6, Some(red.clone())),
+            ]
+        );
+
+        let mut buf = Buffer::no_color();
+        buf.set_color(&red).unwrap();
+        write!(buf, "ab").unwrap();
+        assert!(buf.color_spans().is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{
-        Ansi, Color, ColorSpec, HyperlinkSpec, ParseColorError,
-        ParseColorErrorKind, StandardStream, WriteColor,
-    };
+    #[cfg(windows)]
+    #[test]
+    fn test_buffer_color_spans_windows() {
+        use std::io::Write;
 
-    fn assert_is_send<T: Send>() {}
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
 
-    #[test]
-    fn standard_stream_is_send() {
-        assert_is_send::<StandardStream>();
+        let mut buf = Buffer::console();
+        write!(buf, "a").unwrap();
+        buf.set_color(&red).unwrap();
+        write!(buf, "bc").unwrap();
+        buf.reset().unwrap();
+        write!(buf, "d").unwrap();
+        assert_eq!(buf.color_spans(), vec![(1, Some(red)), (3, None)]);
     }
 
     #[test]
-    fn test_simple_parse_ok() {
-        let color = "green".parse::<Color>();
-        assert_eq!(color, Ok(Color::Green));
+    fn test_pooled_buffer() {
+        use std::io::Write;
+
+        let pool_len = || BUFFER_POOL.with(|pool| pool.borrow().len());
+
+        let mut bufwtr = BufferWriter::stdout(ColorChoice::Never);
+        let mut buf = bufwtr.pooled_buffer();
+        assert!(!buf.supports_color());
+        write!(buf, "{}", "x".repeat(1000)).unwrap();
+        drop(buf);
+        assert_eq!(pool_len(), 1);
+
+        // The storage is reused, but its contents are not.
+        let buf = bufwtr.pooled_buffer();
+        assert_eq!(pool_len(), 0);
+        assert!(buf.is_empty());
+        assert!(buf.as_slice().is_empty());
+        match buf.0 {
+            BufferInner::NoColor(ref b) => assert!(b.wtr.capacity() >= 1000),
+            _ => panic!("expected a buffer without colors"),
+        }
+        drop(buf);
+
+        // The pool never holds more than the configured number of buffers.
+        let bufs: Vec<PooledBuffer> =
+            (0..6).map(|_| bufwtr.pooled_buffer()).collect();
+        drop(bufs);
+        assert_eq!(pool_len(), DEFAULT_POOL_SIZE);
+
+        // Storage from a pooled buffer is reused with the color settings of
+        // the writer that takes it.
+        // It can also be used anywhere a `WriteColor` is expected.
+        fn bold<W: WriteColor>(mut wtr: W) -> io::Result<()> {
+            wtr.set_color(ColorSpec::new().set_bold(true))
+        }
+        let ansi = BufferWriter::stdout(ColorChoice::AlwaysAnsi);
+        let mut buf = ansi.pooled_buffer();
+        assert!(buf.supports_color());
+        bold(&mut buf).unwrap();
+        assert_eq!(buf.as_slice(), b"\x1B[0m\x1B[1m");
+        drop(buf);
+
+        bufwtr.pool_size(0);
+        BUFFER_POOL.with(|pool| pool.borrow_mut().clear());
+        drop(bufwtr.pooled_buffer());
+        assert_eq!(pool_len(), 0);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_256_parse_ok() {
-        let color = "7".parse::<Color>();
-        assert_eq!(color, Ok(Color::Ansi256(7)));
+    fn test_standard_stream_set_lossy() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
 
-        let color = "32".parse::<Color>();
-        assert_eq!(color, Ok(Color::Ansi256(32)));
+        let path = temp_path("set-lossy");
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+        let mut stream =
+            unsafe { StandardStream::from_raw_fd(fd, ColorChoice::Never) };
+        stream.set_lossy(false);
+        stream.write_all(b"a\xFF\xFEb").unwrap();
+        stream.lock().write_all(b"\xC3").unwrap();
+        drop(stream);
 
-        let color = "0xFF".parse::<Color>();
-        assert_eq!(color, Ok(Color::Ansi256(0xFF)));
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, b"a\xFF\xFEb\xC3");
     }
 
+    #[cfg(windows)]
     #[test]
-    fn test_256_parse_err_out_of_range() {
-        let color = "256".parse::<Color>();
-        assert_eq!(
-            color,
-            Err(ParseColorError {
-                kind: ParseColorErrorKind::InvalidAnsi256,
-                given: "256".to_string(),
-            })
-        );
+    fn test_lossy_standard_stream_set_lossy() {
+        use std::io::Write;
+
+        let mut stream =
+            LossyStandardStream { wtr: vec![], is_console: true, lossy: true };
+        stream.write_all(b"a\xFFb").unwrap();
+        assert_eq!(stream.wtr, "a\u{FFFD}b".as_bytes());
+
+        stream.wtr.clear();
+        stream.set_lossy(false);
+        stream.write_all(b"a\xFFb").unwrap();
+        assert_eq!(stream.wtr, b"a\xFFb");
+
+        // Locks inherit the setting.
+        let mut locked = stream.wrap(vec![]);
+        locked.write_all(b"\xFF").unwrap();
+        assert_eq!(locked.wtr, b"\xFF");
     }
 
     #[test]
-    fn test_rgb_parse_ok() {
-        let color = "0,0,0".parse::<Color>();
-        assert_eq!(color, Ok(Color::Rgb(0, 0, 0)));
+    fn test_record() {
+        use std::io::Write;
 
-        let color = "0,128,255".parse::<Color>();
-        assert_eq!(color, Ok(Color::Rgb(0, 128, 255)));
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
 
-        let color = "0x0,0x0,0x0".parse::<Color>();
-        assert_eq!(color, Ok(Color::Rgb(0, 0, 0)));
+        let mut wtr = Record::new(Ansi::new(vec![]));
+        write!(wtr, "a").unwrap();
+        wtr.set_color(&red).unwrap();
+        write!(wtr, "b{}c", 1).unwrap();
+        wtr.set_hyperlink(&HyperlinkSpec::open(b"https://example.com"))
+            .unwrap();
+        wtr.write_all(b"").unwrap();
+        wtr.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        wtr.reset().unwrap();
+        assert_eq!(
+            wtr.ops(),
+            &[
+                Op::Text(b"a".to_vec()),
+                Op::SetColor(red.clone()),
+                Op::Text(b"b1c".to_vec()),
+                Op::SetHyperlink(Some(b"https://example.com".to_vec())),
+                Op::SetHyperlink(None),
+                Op::Reset,
+            ]
+        );
+        assert_eq!(
+            wtr.get_ref().get_ref(),
+            b"a\x1B[0m\x1B[31mb1c\x1B]8;;https://example.com\x1B\\\
+              \x1B]8;;\x1B\\\x1B[0m"
+        );
 
-        let color = "0x33,0x66,0xFF".parse::<Color>();
-        assert_eq!(color, Ok(Color::Rgb(0x33, 0x66, 0xFF)));
+        assert_eq!(wtr.take_ops().len(), 6);
+        assert!(wtr.ops().is_empty());
+
+        // Failed operations are not recorded.
+        let mut wtr = Record::new(NoColor::new(vec![]));
+        wtr.get_mut().strict(true);
+        assert!(wtr.set_color(&red).is_err());
+        assert!(wtr.reset().is_err());
+        assert!(wtr.ops().is_empty());
     }
 
     #[test]
-    fn test_rgb_parse_err_out_of_range() {
-        let color = "0,0,256".parse::<Color>();
+    fn test_color_choice_parse_and_display() {
+        let choices = [
+            ColorChoice::Always,
+            ColorChoice::AlwaysAnsi,
+            ColorChoice::Auto,
+            ColorChoice::Never,
+        ];
+        for &choice in &choices {
+            assert_eq!(
+                choice.to_string().parse::<ColorChoice>().unwrap(),
+                choice
+            );
+        }
+        assert_eq!(ColorChoice::AlwaysAnsi.to_string(), "always-ansi");
         assert_eq!(
-            color,
-            Err(ParseColorError {
-                kind: ParseColorErrorKind::InvalidRgb,
-                given: "0,0,256".to_string(),
-            })
+            "ANSI".parse::<ColorChoice>().unwrap(),
+            ColorChoice::AlwaysAnsi
+        );
+        assert_eq!(
+            "Always-Ansi".parse::<ColorChoice>().unwrap(),
+            ColorChoice::AlwaysAnsi
         );
+        assert_eq!(
+            "NEVER".parse::<ColorChoice>().unwrap(),
+            ColorChoice::Never
+        );
+
+        let err = "Sometimes".parse::<ColorChoice>().unwrap_err();
+        assert_eq!(err.invalid(), "Sometimes");
+        assert!(err.to_string().contains("'Sometimes'"));
+        assert_eq!("".parse::<ColorChoice>().unwrap_err().invalid(), "");
     }
 
     #[test]
-    fn test_rgb_parse_err_bad_format() {
-        let color = "0,0".parse::<Color>();
+    fn test_extended_parse_ok() {
+        let ok = |s: &str, color: Color| {
+            assert_eq!(s.parse(), Ok(color), "{:?}", s)
+        };
+        ok("  Red\t", Color::Red);
+        ok(" 0x10 ", Color::Ansi256(16));
+        ok("1, 2 ,3", Color::Rgb(1, 2, 3));
+        ok("#FF8000", Color::Rgb(255, 128, 0));
+        ok("#f80", Color::Rgb(255, 136, 0));
+        ok("# 0a0B0c", Color::Rgb(10, 11, 12));
+        ok("ansi256(200)", Color::Ansi256(200));
+        ok("ANSI256 ( 0x0A )", Color::Ansi256(10));
+        ok("ansi256:7", Color::Ansi256(7));
+        ok("ansi256: 255", Color::Ansi256(255));
+        ok("rgb(255, 0, 0x0a)", Color::Rgb(255, 0, 10));
+        ok(" RGB( 1,2,3 ) ", Color::Rgb(1, 2, 3));
+        ok("rgb:ff/80/00", Color::Rgb(255, 128, 0));
+        ok("rgb:f/8/0", Color::Rgb(255, 136, 0));
+        ok("rgb:ffff/8000/0000", Color::Rgb(255, 128, 0));
+        ok("rgb:fff/000/800", Color::Rgb(255, 0, 128));
+    }
+
+    #[test]
+    fn test_extended_parse_err() {
+        let err = |s: &str, kind: ParseColorErrorKind| {
+            assert_eq!(
+                s.parse::<Color>(),
+                Err(ParseColorError { kind, given: s.trim().to_string() }),
+                "{:?}",
+                s,
+            );
+        };
+        err("#ff00", ParseColorErrorKind::InvalidRgb);
+        err("#ggg", ParseColorErrorKind::InvalidRgb);
+        err("#", ParseColorErrorKind::InvalidRgb);
+        err("rgb(1,2)", ParseColorErrorKind::InvalidRgb);
+        err("rgb(1,2,256)", ParseColorErrorKind::InvalidRgb);
+        err("rgb:ff/ff", ParseColorErrorKind::InvalidRgb);
+        err("rgb:ff/ff/ff/ff", ParseColorErrorKind::InvalidRgb);
+        err("rgb:fffff/0/0", ParseColorErrorKind::InvalidRgb);
+        err("rgb://", ParseColorErrorKind::InvalidRgb);
+        err("ansi256(256)", ParseColorErrorKind::InvalidAnsi256);
+        err("ansi256:", ParseColorErrorKind::InvalidAnsi256);
+        err("ansi256(1", ParseColorErrorKind::InvalidName);
+
+        let msg = "purple".parse::<Color>().unwrap_err().to_string();
+        for format in &["ansi256(N)", "ansi256:N", "rgb(R,G,B)", "#RRGGBB"] {
+            assert!(msg.contains(format), "{:?} missing {:?}", msg, format);
+        }
+        assert!(msg.contains("#RGB") && msg.contains("rgb:R/G/B"));
+    }
+
+    #[test]
+    fn test_set_reset_sequence() {
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_reset_sequence(Some(b"\x1B[m\x1B]8;;\x1B\\"));
+        buf.set_color(&red).unwrap();
+        buf.reset().unwrap();
+        buf.set_color(&red).unwrap();
         assert_eq!(
-            color,
-            Err(ParseColorError {
-                kind: ParseColorErrorKind::InvalidRgb,
-                given: "0,0".to_string(),
-            })
+            buf.get_ref(),
+            b"\x1B[0m\x1B[31m\x1B[m\x1B]8;;\x1B\\\x1B[0m\x1B[31m"
         );
 
-        let color = "not_a_color".parse::<Color>();
+        // The sequence is carried over to locks of standard streams.
+        let wrapped = buf.wrap(vec![]);
         assert_eq!(
-            color,
-            Err(ParseColorError {
-                kind: ParseColorErrorKind::InvalidName,
-                given: "not_a_color".to_string(),
-            })
+            wrapped.reset_sequence.as_deref(),
+            Some(&b"\x1B[m\x1B]8;;\x1B\\"[..])
         );
-    }
 
-    #[test]
-    fn test_var_ansi_write_rgb() {
         let mut buf = Ansi::new(vec![]);
-        let _ = buf.write_color(true, &Color::Rgb(254, 253, 255), false);
-        assert_eq!(buf.0, b"\x1B[38;2;254;253;255m");
+        buf.set_reset_sequence(Some(b""));
+        buf.reset().unwrap();
+        assert!(buf.get_ref().is_empty());
+
+        // Clearing the sequence restores the default reset.
+        buf.set_reset_sequence(None);
+        buf.reset().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[0m");
     }
 
     #[test]
-    fn test_reset() {
-        let spec = ColorSpec::new();
+    fn test_reset_clears_hyperlink() {
+        let link = HyperlinkSpec::open(b"https://example.com");
+
         let mut buf = Ansi::new(vec![]);
-        buf.set_color(&spec).unwrap();
-        assert_eq!(buf.0, b"\x1B[0m");
+        buf.set_hyperlink(&link).unwrap();
+        buf.reset().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B]8;;https://example.com\x1B\\\x1B[0m");
+
+        let mut buf = Ansi::new(vec![]);
+        buf.set_reset_clears_hyperlink(true);
+        buf.reset().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B[0m");
+        buf.get_mut().clear();
+
+        buf.set_hyperlink(&link).unwrap();
+        buf.reset().unwrap();
+        buf.reset().unwrap();
+        assert_eq!(
+            buf.get_ref(),
+            &b"\x1B]8;;https://example.com\x1B\\\x1B[0m\x1B]8;;\x1B\\\x1B[0m"
+                [..]
+        );
+        buf.get_mut().clear();
+
+        // A link that was closed explicitly isn't closed again.
+        buf.set_hyperlink_with(&[("id", "1")], b"x").unwrap();
+        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+        buf.reset().unwrap();
+        assert_eq!(buf.get_ref(), b"\x1B]8;id=1;x\x1B\\\x1B]8;;\x1B\\\x1B[0m");
     }
 
     #[test]
-    fn test_no_reset() {
+    fn test_is_attr_only() {
         let mut spec = ColorSpec::new();
-        spec.set_reset(false);
+        assert!(!spec.is_attr_only());
+        assert!(spec.is_none());
+
+        for &attrs in &[
+            Attributes::BOLD,
+            Attributes::DIMMED,
+            Attributes::ITALIC,
+            Attributes::UNDERLINE,
+            Attributes::STRIKETHROUGH,
+            Attributes::INTENSE,
+            Attributes::BOLD | Attributes::ITALIC,
+        ] {
+            spec.clear();
+            spec.set_attrs(attrs);
+            assert!(spec.is_attr_only(), "{:?}", attrs);
+            assert!(!spec.is_none());
+        }
 
-        let mut buf = Ansi::new(vec![]);
-        buf.set_color(&spec).unwrap();
-        assert_eq!(buf.0, b"");
+        spec.clear();
+        spec.set_underline(true).set_underline_style(UnderlineStyle::Curly);
+        assert!(spec.is_attr_only());
+        spec.set_bg(Some(Color::Ansi256(1)));
+        assert!(!spec.is_attr_only());
+        spec.set_bg(None).set_fg(Some(Color::Default));
+        assert!(!spec.is_attr_only());
+
+        // The reset setting is not a style.
+        let mut spec = ColorSpec::new();
+        spec.set_reset(false);
+        assert!(!spec.is_attr_only());
     }
 
     #[test]
-    fn test_var_ansi_write_256() {
-        let mut buf = Ansi::new(vec![]);
-        let _ = buf.write_color(false, &Color::Ansi256(7), false);
-        assert_eq!(buf.0, b"\x1B[48;5;7m");
+    fn test_write_fmt_colored() {
+        struct Fails;
 
-        let mut buf = Ansi::new(vec![]);
-        let _ = buf.write_color(false, &Color::Ansi256(208), false);
-        assert_eq!(buf.0, b"\x1B[48;5;208m");
-    }
+        impl std::fmt::Display for Fails {
+            fn fmt(&self, _: &mut std::fmt::Formatter) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
 
-    fn all_attributes() -> Vec<ColorSpec> {
-        let mut result = vec![];
-        for fg in vec![None, Some(Color::Red)] {
-            for bg in vec![None, Some(Color::Red)] {
-                for bold in vec![false, true] {
-                    for underline in vec![false, true] {
-                        for intense in vec![false, true] {
-                            for italic in vec![false, true] {
-                                for strikethrough in vec![false, true] {
-                                    for dimmed in vec![false, true] {
-                                        let mut color = ColorSpec::new();
-                                        color.set_fg(fg);
-                                        color.set_bg(bg);
-                                        color.set_bold(bold);
-                                        color.set_underline(underline);
-                                        color.set_intense(intense);
-                                        color.set_italic(italic);
-                                        color.set_dimmed(dimmed);
-                                        color.set_strikethrough(strikethrough);
-                                        result.push(color);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        let mut red = ColorSpec::new();
+        red.set_fg(Some(Color::Red));
+
+        let mut wtr = Record::new(NoColor::new(vec![]));
+        wtr.write_fmt_colored(&red, format_args!("{}-{}", "a", 1)).unwrap();
+        assert_eq!(
+            wtr.take_ops(),
+            vec![
+                Op::SetColor(red.clone()),
+                Op::Text(b"a-1".to_vec()),
+                Op::Reset,
+            ]
+        );
+
+        // A failing Display impl becomes an error of kind Other, but the
+        // colors are still reset.
+        let err = wtr
+            .write_fmt_colored(&red, format_args!("x{}", Fails))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            wtr.take_ops(),
+            vec![
+                Op::SetColor(red.clone()),
+                Op::Text(b"x".to_vec()),
+                Op::Reset
+            ]
+        );
+
+        // I/O errors are returned as is.
+        let mut buf = [0u8; 4];
+        let mut wtr = Ansi::new(&mut buf[..]);
+        let err = wtr
+            .write_fmt_colored(
+                &ColorSpec::new(),
+                format_args!("{}", "toolong"),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+
+        // The reset still runs when setting the color fails.
+        struct FailsColor;
+
+        impl io::Write for FailsColor {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
             }
         }
-        result
+
+        impl WriteColor for FailsColor {
+            fn supports_color(&self) -> bool {
+                true
+            }
+
+            fn set_color(&mut self, _: &ColorSpec) -> io::Result<()> {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "no colors"))
+            }
+
+            fn reset(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut wtr = Record::new(FailsColor);
+        let err = wtr.write_fmt_colored(&red, format_args!("a")).unwrap_err();
+        assert_eq!(err.to_string(), "no colors");
+        assert_eq!(wtr.ops(), &[Op::Reset]);
     }
 
+    #[cfg(windows)]
     #[test]
-    fn test_is_none() {
-        for (i, color) in all_attributes().iter().enumerate() {
-            assert_eq!(
-                i == 0,
-                color.is_none(),
-                "{:?} => {}",
-                color,
-                color.is_none()
-            )
+    fn test_windows_buffer_print() {
+        use std::io::Write;
+
+        use super::{wincon, ConsoleLike, WindowsBuffer};
+
+        /// A console that records each operation along with the amount of
+        /// text written to the stream when the operation occurred.
+        #[derive(Default)]
+        struct MockConsole {
+            ops: Vec<(usize, String)>,
+            written: std::rc::Rc<std::cell::Cell<usize>>,
+        }
+
+        impl ConsoleLike for MockConsole {
+            fn fg(
+                &mut self,
+                intense: wincon::Intense,
+                color: wincon::Color,
+            ) -> io::Result<()> {
+                let op = format!("fg {:?} {:?}", intense, color);
+                self.ops.push((self.written.get(), op));
+                Ok(())
+            }
+
+            fn bg(
+                &mut self,
+                intense: wincon::Intense,
+                color: wincon::Color,
+            ) -> io::Result<()> {
+                let op = format!("bg {:?} {:?}", intense, color);
+                self.ops.push((self.written.get(), op));
+                Ok(())
+            }
+
+            fn reset(&mut self) -> io::Result<()> {
+                self.ops.push((self.written.get(), "reset".to_string()));
+                Ok(())
+            }
+        }
+
+        struct Stream {
+            buf: Vec<u8>,
+            written: std::rc::Rc<std::cell::Cell<usize>>,
+        }
+
+        impl io::Write for Stream {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.buf.extend_from_slice(buf);
+                self.written.set(self.buf.len());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut buf = WindowsBuffer::new();
+        write!(buf, "ab").unwrap();
+        buf.set_color(
+            ColorSpec::new()
+                .set_fg(Some(Color::Red))
+                .set_bg(Some(Color::Blue))
+                .set_intense(true),
+        )
+        .unwrap();
+        write!(buf, "cde").unwrap();
+        buf.reset().unwrap();
+        buf.set_color(ColorSpec::new().set_fg(Some(Color::Green))).unwrap();
+        write!(buf, "f").unwrap();
+
+        let mut console = MockConsole::default();
+        let mut stream =
+            Stream { buf: vec![], written: console.written.clone() };
+        buf.print(&mut console, &mut stream, false).unwrap();
+        assert_eq!(stream.buf, b"abcdef");
+        assert_eq!(
+            console.ops,
+            vec![
+                (2, "fg Yes Red".to_string()),
+                (2, "bg Yes Blue".to_string()),
+                (5, "reset".to_string()),
+                (5, "fg No Green".to_string()),
+            ]
+        );
+
+        // Bold only makes the foreground intense when opted into.
+        let mut buf = WindowsBuffer::new();
+        buf.set_color(
+            ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true),
+        )
+        .unwrap();
+        for (bold_as_intense, op) in
+            [(false, "fg No Red"), (true, "fg Yes Red")]
+        {
+            let mut console = MockConsole::default();
+            let mut stream =
+                Stream { buf: vec![], written: console.written.clone() };
+            buf.print(&mut console, &mut stream, bold_as_intense).unwrap();
+            assert_eq!(console.ops, vec![(0, op.to_string())]);
         }
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_clear() {
-        for color in all_attributes() {
-            let mut color1 = color.clone();
-            color1.clear();
-            assert!(color1.is_none(), "{:?} => {:?}", color, color1);
+    fn test_standard_stream_into_lock() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
+
+        use super::StandardStreamLock;
+
+        struct Owner {
+            lock: StandardStreamLock<'static>,
         }
+
+        let path = temp_path("into-lock");
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+        let mut stream =
+            unsafe { StandardStream::from_raw_fd(fd, ColorChoice::Always) };
+        stream.set_color(ColorSpec::new().set_fg(Some(Color::Red))).unwrap();
+        write!(stream, "foo").unwrap();
+        let mut owner = Owner { lock: stream.into_lock() };
+        owner.lock.reset().unwrap();
+        write!(owner.lock, "bar").unwrap();
+        drop(owner);
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, b"\x1B[0m\x1B[31mfoo\x1B[0mbar");
     }
 
     #[test]
-    fn test_ansi_hyperlink() {
-        let mut buf = Ansi::new(vec![]);
-        buf.set_hyperlink(&HyperlinkSpec::open(b"https://example.com"))
-            .unwrap();
-        buf.write_str("label").unwrap();
-        buf.set_hyperlink(&HyperlinkSpec::close()).unwrap();
+    fn test_standard_stream_builder() {
+        for &choice in &[ColorChoice::Never, ColorChoice::AlwaysAnsi] {
+            let built =
+                StandardStreamBuilder::new().color_choice(choice).build();
+            let expected = StandardStream::stdout(choice);
+            assert_eq!(format!("{:?}", built), format!("{:?}", expected));
+            assert_eq!(built.supports_color(), expected.supports_color());
+        }
+
+        let mut palette = Palette::new();
+        palette.set(Color::Red, Some(Color::Ansi256(9)));
+        let stream = StandardStreamBuilder::new()
+            .kind(StandardStreamKind::Stderr)
+            .color_choice(ColorChoice::AlwaysAnsi)
+            .color_level(ColorLevel::Basic)
+            .palette(Some(palette))
+            .build();
+        match *stream.wtr.get_ref() {
+            WriterInner::Ansi(ref wtr) => {
+                assert_eq!(wtr.color_level, ColorLevel::Basic);
+                assert_eq!(wtr.palette, Some(palette));
+                assert!(matches!(wtr.wtr, IoStandardStream::Stderr(_)));
+            }
+            _ => panic!("expected an ANSI writer"),
+        }
 
+        let built = StandardStreamBuilder::new()
+            .color_choice(ColorChoice::Always)
+            .bold_as_intense(true)
+            .build();
+        let mut expected = StandardStream::stdout(ColorChoice::Always);
+        expected.set_bold_as_intense(true);
+        assert_eq!(format!("{:?}", built), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn test_color_spec_diff_ansi() {
+        let spec = |s: &str| s.parse::<ColorSpec>().unwrap();
+
+        let red_bold = spec("fg:red bold");
+        assert_eq!(red_bold.diff_ansi(&red_bold), "");
+        assert_eq!(red_bold.diff_ansi(&ColorSpec::new()), "\x1B[1m\x1B[31m");
+        assert_eq!(spec("fg:blue bold").diff_ansi(&red_bold), "\x1B[34m");
+        assert_eq!(ColorSpec::new().diff_ansi(&red_bold), "\x1B[22m\x1B[39m");
         assert_eq!(
-            buf.0,
-            b"\x1B]8;;https://example.com\x1B\\label\x1B]8;;\x1B\\".to_vec()
+            spec("fg:red dimmed").diff_ansi(&red_bold),
+            "\x1B[22m\x1B[2m"
+        );
+        assert_eq!(
+            spec("bold").diff_ansi(&spec("bold dimmed")),
+            "\x1B[22m\x1B[1m"
+        );
+        assert_eq!(
+            spec("bold dimmed italic").diff_ansi(&spec("bold strikethrough")),
+            "\x1B[2m\x1B[3m\x1B[29m"
+        );
+        assert_eq!(
+            spec("underline").diff_ansi(&spec("italic")),
+            "\x1B[23m\x1B[4m"
+        );
+        assert_eq!(
+            spec("italic").diff_ansi(&spec("underline")),
+            "\x1B[3m\x1B[24m"
         );
+        assert_eq!(
+            spec("bg:green").diff_ansi(&spec("fg:red bg:blue")),
+            "\x1B[39m\x1B[42m"
+        );
+        assert_eq!(
+            spec("fg:red").diff_ansi(&spec("bg:blue")),
+            "\x1B[31m\x1B[49m"
+        );
+        assert_eq!(
+            spec("fg:red intense").diff_ansi(&red_bold),
+            "\x1B[22m\x1B[38;5;9m"
+        );
+
+        let mut curly = ColorSpec::new();
+        curly.set_underline(true).set_underline_style(UnderlineStyle::Curly);
+        assert_eq!(curly.diff_ansi(&spec("underline")), "\x1B[4:3m");
+
+        let mut reset = red_bold.clone();
+        reset.set_reset(false);
+        assert_eq!(reset.diff_ansi(&red_bold), "");
+    }
+
+    #[test]
+    fn test_buffer_extend() {
+        for mut buf in [Buffer::no_color(), Buffer::ansi()] {
+            buf.extend(b"foo".iter().map(|b| b.to_ascii_uppercase()));
+            buf.extend(b"bar");
+            buf.extend(Some(b'!'));
+            assert_eq!(buf.as_slice(), b"FOObar!");
+        }
+    }
+
+    #[test]
+    fn test_term_is_dumb() {
+        use std::ffi::OsStr;
+
+        assert!(term_is_dumb(OsStr::new("dumb")));
+        // cygwin is never dumb. On Windows, it only makes the console API
+        // preferred over ANSI escape sequences.
+        for term in &["xterm", "xterm-256color", "cygwin", "dumb-x", ""] {
+            assert!(!term_is_dumb(OsStr::new(term)), "{}", term);
+        }
     }
 }